@@ -7,18 +7,63 @@ use url::Url;
 pub struct Args {
     pub locations: Vec<String>,
 
-    #[arg(long, default_value = "default_secret")]
-    pub secret: String,
+    #[arg(long)]
+    pub secret: Option<String>,
+
+    #[arg(long)]
+    pub mirror: bool,
 
     #[arg(short, long)]
     pub config: Option<String>,
+
+    #[arg(long, value_name = "URL")]
+    pub server: Option<String>,
+
+    #[arg(long, value_name = "PEM")]
+    pub ca_cert: Option<String>,
+
+    #[arg(long, value_name = "SHA256")]
+    pub pin_cert: Option<String>,
+
+    #[arg(long, value_name = "ID|LOCATION")]
+    pub storage: Option<String>,
+
+    #[arg(long, value_name = "PATH")]
+    pub history: Option<String>,
+
+    #[arg(long, value_name = "PATH", requires = "to_version")]
+    pub restore: Option<String>,
+
+    #[arg(long, value_name = "VERSION", requires = "restore")]
+    pub to_version: Option<u64>,
+
+    #[arg(long, value_name = "NAME")]
+    pub create_user: Option<String>,
+
+    #[arg(long, requires = "create_user")]
+    pub admin: bool,
+
+    #[arg(long, value_name = "USER:ROLE")]
+    pub grant: Option<String>,
+
+    #[arg(long, value_name = "PATTERN", num_args = 0..)]
+    pub set_ignore: Option<Vec<String>>,
+
+    #[arg(long, value_name = "PREFIX")]
+    pub include: Vec<String>,
+
+    #[arg(long, value_name = "PREFIX")]
+    pub exclude: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Location {
     Folder(PathBuf),
     Ftp(Url),
-    Zip(PathBuf),
+    Zip { path: PathBuf, writable: bool },
+    Tar(PathBuf),
+    S3(Url),
+    WebDav(Url),
 }
 
 impl Location {
@@ -26,8 +71,18 @@ impl Location {
         if let Some((type_str, path_str)) = input.split_once(':') {
             match type_str {
                 "folder" => Ok(Location::Folder(PathBuf::from(path_str))),
-                "zip" => Ok(Location::Zip(PathBuf::from(path_str))),
-                "ftp" => {
+                "zip" => Ok(Location::Zip { path: PathBuf::from(path_str), writable: false }),
+                "zip+rw" => Ok(Location::Zip { path: PathBuf::from(path_str), writable: true }),
+                "tar" => Ok(Location::Tar(PathBuf::from(path_str))),
+                "s3" => {
+                    let url = Url::parse(input).map_err(|e| e.to_string())?;
+                    Ok(Location::S3(url))
+                },
+                "webdav" | "webdavs" => {
+                    let url = Url::parse(input).map_err(|e| e.to_string())?;
+                    Ok(Location::WebDav(url))
+                },
+                "ftp" | "ftpes" | "ftps" => {
                     let url = Url::parse(input).map_err(|e| e.to_string())?;
                     Ok(Location::Ftp(url))
                 },
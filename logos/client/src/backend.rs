@@ -1,6 +1,7 @@
 use common::FileMetadata;
 use anyhow::Result;
 use async_trait::async_trait;
+use std::path::Path;
 
 #[async_trait]
 pub trait StorageBackend: Send + Sync {
@@ -8,6 +9,22 @@ pub trait StorageBackend: Send + Sync {
     async fn read_file(&self, path: &str) -> Result<Vec<u8>>;
     async fn write_file(&self, path: &str, content: &[u8]) -> Result<()>;
     async fn delete_file(&self, path: &str) -> Result<()>;
+
+    async fn read_range(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let content = self.read_file(path).await?;
+        let start = (offset as usize).min(content.len());
+        let end = start.saturating_add(len).min(content.len());
+        Ok(content[start..end].to_vec())
+    }
+
+    async fn stat(&self, path: &str) -> Result<Option<FileMetadata>> {
+        Ok(self.list_files().await?.into_iter().find(|f| f.path == path))
+    }
+
+    async fn write_from(&self, path: &str, source: &Path) -> Result<()> {
+        let content = tokio::fs::read(source).await?;
+        self.write_file(path, &content).await
+    }
     
     fn is_read_only(&self) -> bool { false }
-}
\ No newline at end of file
+}
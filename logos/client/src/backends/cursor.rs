@@ -0,0 +1,39 @@
+use anyhow::{Result, anyhow};
+use std::io::{self, Read};
+use std::sync::Mutex;
+
+struct Position {
+    path: String,
+    offset: u64,
+    reader: Box<dyn Read + Send>,
+}
+
+#[derive(Default)]
+pub struct ReadCursor {
+    current: Mutex<Option<Position>>,
+}
+
+impl ReadCursor {
+    pub fn read(&self, path: &str, offset: u64, len: usize, open: impl FnOnce() -> Result<Box<dyn Read + Send>>) -> Result<Vec<u8>> {
+        let mut current = self.current.lock().map_err(|_| anyhow!("Read cursor poisoned"))?;
+        let mut position = match current.take() {
+            Some(p) if p.path == path && p.offset <= offset => p,
+            _ => Position { path: path.to_string(), offset: 0, reader: open()? },
+        };
+
+        io::copy(&mut (&mut position.reader).take(offset - position.offset), &mut io::sink())?;
+        let mut buf = Vec::with_capacity(len);
+        (&mut position.reader).take(len as u64).read_to_end(&mut buf)?;
+        position.offset = offset + buf.len() as u64;
+        if buf.len() == len {
+            *current = Some(position);
+        }
+        Ok(buf)
+    }
+
+    pub fn reset(&self) {
+        if let Ok(mut current) = self.current.lock() {
+            *current = None;
+        }
+    }
+}
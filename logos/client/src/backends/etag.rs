@@ -0,0 +1,41 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+// A changed ETag with an unchanged Last-Modified still has to break the size/mtime fingerprint,
+// so such a change is reported one second after the last reported mtime. The map lives in memory
+// only: after a restart the fingerprint falls back to the server's Last-Modified and can differ
+// from the stored size/mtime once.
+#[derive(Default)]
+pub struct EtagTracker {
+    seen: Mutex<HashMap<String, (String, u64)>>,
+}
+
+impl EtagTracker {
+    pub fn observe(&self, path: &str, etag: Option<&str>, modified: u64) -> u64 {
+        let Some(etag) = etag else { return modified };
+        let Ok(mut seen) = self.seen.lock() else { return modified };
+        let modified = match seen.get(path) {
+            Some((known, reported)) if known == etag => (*reported).max(modified),
+            Some((_, reported)) if *reported >= modified => reported + 1,
+            _ => modified,
+        };
+        seen.insert(path.to_string(), (etag.to_string(), modified));
+        modified
+    }
+
+    pub fn etag(&self, path: &str) -> Option<String> {
+        self.seen.lock().ok().and_then(|seen| seen.get(path).map(|(etag, _)| etag.clone()))
+    }
+
+    pub fn forget(&self, path: &str) {
+        if let Ok(mut seen) = self.seen.lock() {
+            seen.remove(path);
+        }
+    }
+
+    pub fn retain(&self, present: &HashSet<String>) {
+        if let Ok(mut seen) = self.seen.lock() {
+            seen.retain(|path, _| present.contains(path));
+        }
+    }
+}
@@ -2,8 +2,9 @@ use crate::backend::StorageBackend;
 use common::FileMetadata;
 use anyhow::{Result, Context};
 use async_trait::async_trait;
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
 use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use walkdir::WalkDir;
 
 pub struct FolderBackend {
@@ -37,8 +38,6 @@ impl StorageBackend for FolderBackend {
                     let path = entry.path().strip_prefix(&root).unwrap()
                         .to_string_lossy()
                         .replace("\\", "/");
-                    
-                    if path.starts_with(".git") { continue; }
 
                     list.push(FileMetadata {
                         path,
@@ -49,6 +48,7 @@ impl StorageBackend for FolderBackend {
                         hash: String::new(),
                         is_deleted: false,
                         last_modified_by: None,
+                        chunks: Vec::new(),
                     });
                 }
             }
@@ -58,6 +58,26 @@ impl StorageBackend for FolderBackend {
         Ok(files)
     }
 
+    async fn stat(&self, path: &str) -> Result<Option<FileMetadata>> {
+        let meta = match fs::metadata(self.resolve(path)).await {
+            Ok(meta) if meta.is_file() => meta,
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(FileMetadata {
+            path: path.to_string(),
+            size: meta.len(),
+            modified: meta.modified().unwrap_or(std::time::SystemTime::UNIX_EPOCH)
+                .duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+            version: 0,
+            hash: String::new(),
+            is_deleted: false,
+            last_modified_by: None,
+            chunks: Vec::new(),
+        }))
+    }
+
     async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
         fs::read(self.resolve(path)).await.context("fs read failed")
     }
@@ -82,6 +102,23 @@ impl StorageBackend for FolderBackend {
         }
     }
 
+    async fn read_range(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let mut file = fs::File::open(self.resolve(path)).await.context("fs open failed")?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        let mut buf = Vec::with_capacity(len);
+        file.take(len as u64).read_to_end(&mut buf).await?;
+        Ok(buf)
+    }
+
+    async fn write_from(&self, path: &str, source: &Path) -> Result<()> {
+        let target = self.resolve(path);
+        if let Some(p) = target.parent() {
+            fs::create_dir_all(p).await?;
+        }
+        fs::copy(source, &target).await.context("fs copy failed")?;
+        Ok(())
+    }
+
     async fn delete_file(&self, path: &str) -> Result<()> {
         let target = self.resolve(path);
         if target.exists() {
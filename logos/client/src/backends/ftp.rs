@@ -2,35 +2,138 @@ use crate::backend::StorageBackend;
 use common::FileMetadata;
 use anyhow::{Result, Context, anyhow};
 use async_trait::async_trait;
-use suppaftp::FtpStream;
+use suppaftp::{Mode, NativeTlsConnector, NativeTlsFtpStream as FtpStream, Status};
+use suppaftp::list::File;
+use suppaftp::native_tls::TlsConnector;
 use std::sync::{Arc, Mutex};
 use url::Url;
-use std::io::Cursor;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use crate::connection::load_ca_bundle;
 
 pub struct FtpBackend {
     conn: Arc<Mutex<FtpStream>>,
-    root: String,
+    use_mlsd: bool,
+}
+
+fn parent_dirs(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/').map(move |(i, _)| &path[..i])
+}
+
+fn list_dir(ftp: &mut FtpStream, dir: &str, use_mlsd: bool) -> Result<Vec<File>> {
+    let target = if dir.is_empty() { None } else { Some(dir) };
+    let entries = if use_mlsd {
+        ftp.mlsd(target)?.iter().filter_map(|line| File::from_mlsx_line(line).ok()).collect()
+    } else {
+        ftp.list(target)?.iter().filter_map(|line| File::try_from(line.as_str()).ok()).collect()
+    };
+    Ok(entries)
+}
+
+fn ensure_parent_dirs(ftp: &mut FtpStream, path: &str) {
+    for dir in parent_dirs(path) {
+        let _ = ftp.mkdir(dir);
+    }
+}
+
+fn remove_empty_parents(ftp: &mut FtpStream, path: &str) {
+    let dirs: Vec<&str> = parent_dirs(path).collect();
+    for dir in dirs.into_iter().rev() {
+        if ftp.rmdir(dir).is_err() { break; }
+    }
+}
+
+#[derive(PartialEq)]
+enum Security {
+    Plain,
+    Explicit,
+    Implicit,
+}
+
+fn tls_connector(ca_cert: Option<&str>, verify: bool) -> Result<NativeTlsConnector> {
+    let mut builder = TlsConnector::builder();
+    if let Some(path) = ca_cert {
+        for cert in load_ca_bundle(path)? {
+            builder.add_root_certificate(cert);
+        }
+    }
+    if !verify {
+        builder.danger_accept_invalid_certs(true).danger_accept_invalid_hostnames(true);
+    }
+    Ok(NativeTlsConnector::from(builder.build().context("Failed to build TLS connector")?))
 }
 
 impl FtpBackend {
     pub fn new(raw_url: &str) -> Result<Self> {
         let url = Url::parse(raw_url.trim()).context("Invalid URL")?;
-        if url.scheme() != "ftp" { return Err(anyhow!("Scheme must be ftp")); }
+        let security = match url.scheme() {
+            "ftp" => Security::Plain,
+            "ftpes" => Security::Explicit,
+            "ftps" => Security::Implicit,
+            _ => return Err(anyhow!("Scheme must be ftp, ftpes or ftps")),
+        };
 
         let host = url.host_str().context("No host")?;
-        let port = url.port().unwrap_or(21);
+        let port = url.port().unwrap_or(if security == Security::Implicit { 990 } else { 21 });
         let user = url.username();
         let pass = url.password().unwrap_or("anonymous");
         let root = url.path().to_string();
 
-        let mut stream = FtpStream::connect(format!("{}:{}", host, port))?;
+        let mut mode = Mode::Passive;
+        let mut ca_cert = None;
+        let mut verify = true;
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "mode" => mode = match value.as_ref() {
+                    "active" => Mode::Active,
+                    "passive" => Mode::Passive,
+                    "epsv" => Mode::ExtendedPassive,
+                    other => return Err(anyhow!("Unknown FTP mode: {}", other)),
+                },
+                "ca_cert" => ca_cert = Some(value.to_string()),
+                "verify" => verify = !matches!(value.as_ref(), "0" | "false" | "no"),
+                other => return Err(anyhow!("Unknown FTP option: {}", other)),
+            }
+        }
+        if security == Security::Plain && (ca_cert.is_some() || !verify) {
+            return Err(anyhow!("Certificate options require an ftpes:// or ftps:// location"));
+        }
+
+        let addr = format!("{}:{}", host, port);
+        let mut stream = match security {
+            Security::Plain => FtpStream::connect(&addr)?,
+            Security::Explicit => FtpStream::connect(&addr)?
+                .into_secure(tls_connector(ca_cert.as_deref(), verify)?, host)
+                .context("TLS negotiation failed")?,
+            Security::Implicit => {
+                let mut stream = FtpStream::connect_secure_implicit(&addr, tls_connector(ca_cert.as_deref(), verify)?, host)
+                    .context("TLS connection failed")?;
+                stream.custom_command("PBSZ 0", &[Status::CommandOk])?;
+                stream.custom_command("PROT P", &[Status::CommandOk])?;
+                stream
+            }
+        };
+        stream.set_mode(mode);
+        if !verify {
+            println!("[!] FTP certificate verification is disabled for {}", host);
+        }
         if !user.is_empty() {
             stream.login(user, pass)?;
         }
 
+        if !root.is_empty() && root != "/" {
+            let absolute = format!("{}/", root.trim_end_matches('/'));
+            for dir in parent_dirs(&absolute).filter(|d| !d.is_empty()) {
+                let _ = stream.mkdir(dir);
+            }
+            stream.cwd(&root).context(format!("Failed to enter FTP root {}", root))?;
+        }
+
+        let use_mlsd = stream.feat().map(|f| f.contains_key("MLSD") || f.contains_key("MLST")).unwrap_or(false);
+
         Ok(Self {
             conn: Arc::new(Mutex::new(stream)),
-            root,
+            use_mlsd,
         })
     }
 }
@@ -39,37 +142,69 @@ impl FtpBackend {
 impl StorageBackend for FtpBackend {
     async fn list_files(&self) -> Result<Vec<FileMetadata>> {
         let c = self.conn.clone();
-        let r = self.root.clone();
+        let use_mlsd = self.use_mlsd;
 
         tokio::task::spawn_blocking(move || {
             let mut ftp = c.lock().map_err(|_| anyhow!("FTP Mutex poisoned"))?;
-            if !r.is_empty() && r != "/" {
-                let _ = ftp.cwd(&r);
-            }
-
-            let names = ftp.nlst(None)?;
             let mut files = Vec::new();
+            let mut pending = vec![String::new()];
+
+            while let Some(dir) = pending.pop() {
+                let entries = match list_dir(&mut ftp, &dir, use_mlsd) {
+                    Ok(entries) => entries,
+                    Err(e) if dir.is_empty() => return Err(e),
+                    Err(e) => {
+                        eprintln!("[!] Failed to list {}: {}", dir, e);
+                        continue;
+                    }
+                };
 
-            for n in names {
-                if n == "." || n == ".." { continue; }
-                
-                let size = ftp.size(&n).unwrap_or(0) as u64;
-                let mod_time = ftp.mdtm(&n).map(|t| t.and_utc().timestamp() as u64).unwrap_or(0);
-
-                files.push(FileMetadata {
-                    path: n,
-                    size, 
-                    modified: mod_time,
-                    version: 0,
-                    hash: String::new(),
-                    is_deleted: false,
-                    last_modified_by: None,
-                });
+                for entry in entries {
+                    let name = entry.name();
+                    if name == "." || name == ".." { continue; }
+                    let path = if dir.is_empty() { name.to_string() } else { format!("{}/{}", dir, name) };
+
+                    if entry.is_directory() {
+                        pending.push(path);
+                    } else if entry.is_file() {
+                        files.push(FileMetadata {
+                            path,
+                            size: entry.size() as u64,
+                            modified: entry.modified().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+                            version: 0,
+                            hash: String::new(),
+                            is_deleted: false,
+                            last_modified_by: None,
+                            chunks: Vec::new(),
+                        });
+                    }
+                }
             }
             Ok(files)
         }).await?
     }
 
+    async fn stat(&self, path: &str) -> Result<Option<FileMetadata>> {
+        let c = self.conn.clone();
+        let p = path.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut ftp = c.lock().map_err(|_| anyhow!("FTP Mutex poisoned"))?;
+            let Ok(size) = ftp.size(&p) else { return Ok(None) };
+            let modified = ftp.mdtm(&p).map(|t| t.and_utc().timestamp() as u64).unwrap_or(0);
+            Ok(Some(FileMetadata {
+                path: p,
+                size: size as u64,
+                modified,
+                version: 0,
+                hash: String::new(),
+                is_deleted: false,
+                last_modified_by: None,
+                chunks: Vec::new(),
+            }))
+        }).await?
+    }
+
     async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
         let c = self.conn.clone();
         let p = path.to_string();
@@ -85,6 +220,27 @@ impl StorageBackend for FtpBackend {
         }).await?
     }
 
+    async fn read_range(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let c = self.conn.clone();
+        let p = path.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut ftp = c.lock().map_err(|_| anyhow!("FTP Mutex poisoned"))?;
+            if offset > 0 {
+                ftp.resume_transfer(offset as usize)?;
+            }
+            let mut stream = ftp.retr_as_stream(&p)?;
+            let mut buf = Vec::with_capacity(len);
+            (&mut stream).take(len as u64).read_to_end(&mut buf)?;
+            if buf.len() < len {
+                ftp.finalize_retr_stream(stream)?;
+            } else if let Err(e) = ftp.abort(stream) {
+                ftp.noop().context(format!("Failed to abort transfer of {}: {}", p, e))?;
+            }
+            Ok(buf)
+        }).await?
+    }
+
     async fn write_file(&self, path: &str, content: &[u8]) -> Result<()> {
         let c = self.conn.clone();
         let p = path.to_string();
@@ -92,12 +248,27 @@ impl StorageBackend for FtpBackend {
 
         tokio::task::spawn_blocking(move || {
             let mut ftp = c.lock().map_err(|_| anyhow!("FTP Mutex poisoned"))?;
+            ensure_parent_dirs(&mut ftp, &p);
             let mut r = Cursor::new(d);
             ftp.put_file(&p, &mut r)?;
             Ok(())
         }).await?
     }
 
+    async fn write_from(&self, path: &str, source: &Path) -> Result<()> {
+        let c = self.conn.clone();
+        let p = path.to_string();
+        let src = source.to_path_buf();
+
+        tokio::task::spawn_blocking(move || {
+            let mut ftp = c.lock().map_err(|_| anyhow!("FTP Mutex poisoned"))?;
+            ensure_parent_dirs(&mut ftp, &p);
+            let mut r = std::fs::File::open(&src)?;
+            ftp.put_file(&p, &mut r)?;
+            Ok(())
+        }).await?
+    }
+
     async fn delete_file(&self, path: &str) -> Result<()> {
         let c = self.conn.clone();
         let p = path.to_string();
@@ -105,6 +276,7 @@ impl StorageBackend for FtpBackend {
         tokio::task::spawn_blocking(move || {
             let mut ftp = c.lock().map_err(|_| anyhow!("FTP Mutex poisoned"))?;
             ftp.rm(&p)?;
+            remove_empty_parents(&mut ftp, &p);
             Ok(())
         }).await?
     }
@@ -1,4 +1,9 @@
+pub mod cursor;
+pub mod etag;
 pub mod folder;
 pub mod ftp;
+pub mod s3;
 pub mod sftp;
+pub mod tar;
+pub mod webdav;
 pub mod zip;
\ No newline at end of file
@@ -0,0 +1,252 @@
+use crate::backend::StorageBackend;
+use crate::backends::etag::EtagTracker;
+use common::FileMetadata;
+use anyhow::{Result, Context, anyhow};
+use async_trait::async_trait;
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_s3::Client;
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::config::Credentials;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use std::collections::HashSet;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+use url::Url;
+
+const MULTIPART_THRESHOLD: u64 = 16 << 20;
+const MIN_PART_SIZE: u64 = 8 << 20;
+const MAX_PARTS: u64 = 10_000;
+
+#[derive(Default)]
+pub struct S3Options {
+    pub endpoint: Option<String>,
+    pub region: Option<String>,
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    pub path_style: Option<bool>,
+}
+
+fn sdk_error(operation: &str, path: &str, e: impl std::error::Error) -> anyhow::Error {
+    anyhow!("{} failed for {}: {}", operation, path, DisplayErrorContext(e))
+}
+
+pub struct S3Backend {
+    client: Client,
+    bucket: String,
+    prefix: String,
+    etags: EtagTracker,
+}
+
+fn seconds(modified: Option<&aws_sdk_s3::primitives::DateTime>) -> u64 {
+    modified.map(|t| t.secs().max(0) as u64).unwrap_or(0)
+}
+
+fn file_entry(path: String, size: Option<i64>, modified: u64) -> FileMetadata {
+    FileMetadata {
+        path,
+        size: size.unwrap_or(0).max(0) as u64,
+        modified,
+        version: 0,
+        hash: String::new(),
+        is_deleted: false,
+        last_modified_by: None,
+        chunks: Vec::new(),
+    }
+}
+
+impl S3Backend {
+    pub async fn new(url: &Url, options: S3Options) -> Result<Self> {
+        let bucket = url.host_str().context("No bucket provided")?.to_string();
+        let prefix = url.path().trim_matches('/').to_string();
+
+        let region = RegionProviderChain::first_try(options.region.map(Region::new))
+            .or_default_provider()
+            .or_else(Region::new("us-east-1"));
+        let mut loader = aws_config::defaults(BehaviorVersion::latest()).region(region);
+        if let Some(endpoint) = &options.endpoint {
+            loader = loader.endpoint_url(endpoint);
+        }
+        match (options.access_key, options.secret_key) {
+            (Some(access), Some(secret)) => {
+                loader = loader.credentials_provider(Credentials::new(access, secret, None, None, "logos-config"));
+            }
+            (None, None) => {}
+            _ => return Err(anyhow!("Both s3_access_key and s3_secret_key must be set")),
+        }
+        let shared = loader.load().await;
+
+        let path_style = options.path_style.unwrap_or(options.endpoint.is_some());
+        let config = aws_sdk_s3::config::Builder::from(&shared).force_path_style(path_style).build();
+        let client = Client::from_conf(config);
+
+        client.head_bucket().bucket(&bucket).send().await
+            .map_err(|e| sdk_error("HeadBucket", &bucket, e))?;
+
+        Ok(Self {
+            client,
+            bucket,
+            prefix,
+            etags: EtagTracker::default(),
+        })
+    }
+
+    fn key(&self, path: &str) -> String {
+        if self.prefix.is_empty() { path.to_string() } else { format!("{}/{}", self.prefix, path) }
+    }
+
+    async fn refresh(&self, path: &str) {
+        let _ = self.stat(path).await;
+    }
+
+    async fn upload_multipart(&self, path: &str, source: &Path, size: u64) -> Result<()> {
+        let key = self.key(path);
+        let upload = self.client.create_multipart_upload().bucket(&self.bucket).key(&key).send().await
+            .map_err(|e| sdk_error("CreateMultipartUpload", path, e))?;
+        let upload_id = upload.upload_id().context("S3 returned no upload id")?.to_string();
+
+        let part_size = MIN_PART_SIZE.max(size.div_ceil(MAX_PARTS));
+        let result = async {
+            let mut file = tokio::fs::File::open(source).await?;
+            let mut parts = Vec::new();
+            let mut part_number = 1;
+            loop {
+                let mut buf = Vec::with_capacity(part_size as usize);
+                (&mut file).take(part_size).read_to_end(&mut buf).await?;
+                if buf.is_empty() { break; }
+                let part = self.client.upload_part()
+                    .bucket(&self.bucket).key(&key).upload_id(&upload_id)
+                    .part_number(part_number)
+                    .body(ByteStream::from(buf))
+                    .send().await
+                    .map_err(|e| sdk_error("UploadPart", path, e))?;
+                parts.push(CompletedPart::builder().set_e_tag(part.e_tag).part_number(part_number).build());
+                part_number += 1;
+            }
+            self.client.complete_multipart_upload()
+                .bucket(&self.bucket).key(&key).upload_id(&upload_id)
+                .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+                .send().await
+                .map_err(|e| sdk_error("CompleteMultipartUpload", path, e))?;
+            Ok::<_, anyhow::Error>(())
+        }.await;
+
+        match result {
+            Ok(()) => {
+                self.refresh(path).await;
+                Ok(())
+            }
+            Err(e) => {
+                let _ = self.client.abort_multipart_upload()
+                    .bucket(&self.bucket).key(&key).upload_id(&upload_id)
+                    .send().await;
+                Err(e)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for S3Backend {
+    async fn list_files(&self) -> Result<Vec<FileMetadata>> {
+        let prefix = if self.prefix.is_empty() { String::new() } else { format!("{}/", self.prefix) };
+        let mut files = Vec::new();
+        let mut seen = HashSet::new();
+        let mut token = None;
+
+        loop {
+            let page = self.client.list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&prefix)
+                .set_continuation_token(token)
+                .send().await
+                .map_err(|e| sdk_error("ListObjectsV2", &prefix, e))?;
+
+            for object in page.contents() {
+                let Some(path) = object.key().and_then(|k| k.strip_prefix(prefix.as_str())) else { continue };
+                if path.is_empty() || path.ends_with('/') { continue; }
+                let modified = self.etags.observe(path, object.e_tag(), seconds(object.last_modified()));
+                seen.insert(path.to_string());
+                files.push(file_entry(path.to_string(), object.size(), modified));
+            }
+
+            token = page.next_continuation_token().map(str::to_string);
+            if !page.is_truncated().unwrap_or(false) || token.is_none() { break; }
+        }
+
+        self.etags.retain(&seen);
+        Ok(files)
+    }
+
+    async fn stat(&self, path: &str) -> Result<Option<FileMetadata>> {
+        match self.client.head_object().bucket(&self.bucket).key(self.key(path)).send().await {
+            Ok(head) => {
+                let modified = self.etags.observe(path, head.e_tag(), seconds(head.last_modified()));
+                Ok(Some(file_entry(path.to_string(), head.content_length(), modified)))
+            }
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => {
+                self.etags.forget(path);
+                Ok(None)
+            }
+            Err(e) => Err(sdk_error("HeadObject", path, e)),
+        }
+    }
+
+    async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        let object = self.client.get_object().bucket(&self.bucket).key(self.key(path)).send().await
+            .map_err(|e| sdk_error("GetObject", path, e))?;
+        self.etags.observe(path, object.e_tag(), seconds(object.last_modified()));
+        Ok(object.body.collect().await?.into_bytes().to_vec())
+    }
+
+    async fn read_range(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let object = self.client.get_object()
+            .bucket(&self.bucket)
+            .key(self.key(path))
+            .range(format!("bytes={}-{}", offset, offset + len as u64 - 1))
+            .set_if_match(self.etags.etag(path))
+            .send().await;
+        match object {
+            Ok(object) => Ok(object.body.collect().await?.into_bytes().to_vec()),
+            Err(e) if e.raw_response().is_some_and(|r| r.status().as_u16() == 416) => Ok(Vec::new()),
+            Err(e) => Err(sdk_error("GetObject", path, e)),
+        }
+    }
+
+    async fn write_file(&self, path: &str, content: &[u8]) -> Result<()> {
+        self.client.put_object()
+            .bucket(&self.bucket)
+            .key(self.key(path))
+            .body(ByteStream::from(content.to_vec()))
+            .send().await
+            .map_err(|e| sdk_error("PutObject", path, e))?;
+        self.refresh(path).await;
+        Ok(())
+    }
+
+    async fn write_from(&self, path: &str, source: &Path) -> Result<()> {
+        let size = tokio::fs::metadata(source).await?.len();
+        if size >= MULTIPART_THRESHOLD {
+            return self.upload_multipart(path, source, size).await;
+        }
+        self.client.put_object()
+            .bucket(&self.bucket)
+            .key(self.key(path))
+            .body(ByteStream::from_path(source).await?)
+            .send().await
+            .map_err(|e| sdk_error("PutObject", path, e))?;
+        self.refresh(path).await;
+        Ok(())
+    }
+
+    async fn delete_file(&self, path: &str) -> Result<()> {
+        self.client.delete_object().bucket(&self.bucket).key(self.key(path)).send().await
+            .map_err(|e| sdk_error("DeleteObject", path, e))?;
+        self.etags.forget(path);
+        Ok(())
+    }
+}
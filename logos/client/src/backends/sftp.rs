@@ -6,27 +6,170 @@ use url::Url;
 use std::sync::Arc;
 use russh::*;
 use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::FileType;
 use russh_keys::*;
 use percent_encoding::percent_decode_str;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::collections::HashSet;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use dialoguer::{theme::ColorfulTheme, Confirm, Password};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
-struct ClientHandler;
+struct ClientHandler {
+    host: String,
+    port: u16,
+    known_hosts: PathBuf,
+}
 
 #[async_trait]
 impl client::Handler for ClientHandler {
-   type Error = russh::Error;
-   async fn check_server_key(&mut self, _key: &key::PublicKey) -> Result<bool, Self::Error> {
-       Ok(true) 
-   }
+    type Error = anyhow::Error;
+
+    async fn check_server_key(&mut self, key: &key::PublicKey) -> Result<bool, Self::Error> {
+        match check_known_hosts_path(&self.host, self.port, key, &self.known_hosts) {
+            Ok(true) => return Ok(true),
+            Ok(false) => {}
+            Err(russh_keys::Error::KeyChanged { line }) => {
+                return Err(anyhow!(
+                    "Host key for {}:{} does not match {} line {}. Someone may be intercepting the connection; refusing to connect.",
+                    self.host, self.port, self.known_hosts.display(), line
+                ));
+            }
+            Err(e) => return Err(anyhow!("Failed to read {}: {}", self.known_hosts.display(), e)),
+        }
+
+        let fingerprint = key.fingerprint();
+        println!("[!] Unknown host key for {}:{}", self.host, self.port);
+        println!("    {} SHA256:{}", key.name(), fingerprint);
+        if !std::io::stdin().is_terminal() {
+            return Err(anyhow!("Host {}:{} is not in {}", self.host, self.port, self.known_hosts.display()));
+        }
+
+        let prompt = format!("Trust {}:{} and add it to {}?", self.host, self.port, self.known_hosts.display());
+        let trusted = tokio::task::spawn_blocking(move || {
+            Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(prompt)
+                .default(false)
+                .interact()
+        }).await??;
+        if !trusted {
+            return Err(anyhow!("Host key for {}:{} rejected", self.host, self.port));
+        }
+
+        learn_known_hosts_path(&self.host, self.port, key, &self.known_hosts)?;
+        println!("[+] Added {}:{} to {}", self.host, self.port, self.known_hosts.display());
+        Ok(true)
+    }
+}
+
+#[derive(Default)]
+pub struct SshOptions {
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub key_file: Option<PathBuf>,
+    pub known_hosts: Option<PathBuf>,
+}
+
+fn ssh_dir() -> PathBuf {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")).unwrap_or_default();
+    PathBuf::from(home).join(".ssh")
+}
+
+pub fn default_known_hosts() -> PathBuf {
+    ssh_dir().join("known_hosts")
+}
+
+async fn load_key(path: &Path, prompt: bool) -> Result<key::KeyPair> {
+    match load_secret_key(path, None) {
+        Ok(key) => Ok(key),
+        Err(russh_keys::Error::KeyIsEncrypted) => {
+            if !prompt {
+                return Err(anyhow!("Key {} is encrypted and a password is configured", path.display()));
+            }
+            if !std::io::stdin().is_terminal() {
+                return Err(anyhow!("Key {} is encrypted and no terminal is available for the passphrase", path.display()));
+            }
+            let prompt = format!("Passphrase for {}", path.display());
+            let passphrase = tokio::task::spawn_blocking(move || {
+                Password::with_theme(&ColorfulTheme::default())
+                    .with_prompt(prompt)
+                    .interact()
+            }).await??;
+            load_secret_key(path, Some(&passphrase))
+                .with_context(|| format!("Failed to decrypt {}", path.display()))
+        }
+        Err(e) => Err(anyhow!("Failed to load key {}: {}", path.display(), e)),
+    }
+}
+
+async fn authenticate(session: &mut client::Handle<ClientHandler>, user: &str, options: &SshOptions) -> Result<()> {
+    if let Ok(mut agent) = agent::client::AgentClient::connect_env().await {
+        let identities = agent.request_identities().await.unwrap_or_default();
+        for key in identities {
+            let fingerprint = key.fingerprint();
+            let (returned, result) = session.authenticate_future(user, key, agent).await;
+            agent = returned;
+            if result.unwrap_or(false) {
+                println!("[+] Authenticated as {} with agent key SHA256:{}", user, fingerprint);
+                return Ok(());
+            }
+        }
+    }
+
+    let key_files = match &options.key_file {
+        Some(path) => vec![path.clone()],
+        None => ["id_ed25519", "id_ecdsa", "id_rsa"].iter()
+            .map(|name| ssh_dir().join(name))
+            .filter(|path| path.exists())
+            .collect(),
+    };
+    let prompt = options.key_file.is_some() || options.password.is_none();
+    for path in key_files {
+        let key = match load_key(&path, prompt).await {
+            Ok(key) => key,
+            Err(e) => {
+                eprintln!("[!] Skipping SSH key: {:#}", e);
+                continue;
+            }
+        };
+        if session.authenticate_publickey(user, Arc::new(key)).await? {
+            println!("[+] Authenticated as {} with key {}", user, path.display());
+            return Ok(());
+        }
+    }
+
+    if let Some(password) = &options.password
+        && session.authenticate_password(user, password).await?
+    {
+        println!("[+] Authenticated as {} with password", user);
+        return Ok(());
+    }
+
+    Err(anyhow!("Authentication failed for user: {}", user))
 }
 
 pub struct SftpBackend {
     sftp: SftpSession,
     root_path: String,
+    follow_symlinks: bool,
+    max_depth: Option<usize>,
+}
+
+fn file_entry(path: String, meta: &russh_sftp::client::fs::Metadata) -> FileMetadata {
+    FileMetadata {
+        path,
+        size: meta.size.unwrap_or(0),
+        modified: meta.mtime.unwrap_or(0) as u64,
+        version: 0,
+        hash: String::new(),
+        is_deleted: false,
+        last_modified_by: None,
+        chunks: Vec::new(),
+    }
 }
 
 impl SftpBackend {
-    pub async fn new(url_str: &str) -> Result<Self> {
+    pub async fn new(url_str: &str, mut options: SshOptions) -> Result<Self> {
         let url = Url::parse(url_str.trim()).context("Invalid URL")?;
 
         if url.scheme() != "sftp" && url.scheme() != "ssh" {
@@ -36,21 +179,41 @@ impl SftpBackend {
         let host = url.host_str().context("No host provided")?;
         let port = url.port().unwrap_or(22);
         
-        let user = percent_decode_str(url.username()).decode_utf8_lossy().to_string();
-        let pass = percent_decode_str(url.password().unwrap_or("")).decode_utf8_lossy().to_string();
+        if let Some(pass) = url.password() {
+            println!("[!] Passwords in the location URL are deprecated; set ssh_password in the config instead.");
+            options.password.get_or_insert_with(|| percent_decode_str(pass).decode_utf8_lossy().to_string());
+        }
+        let user = match options.user.take() {
+            _ if !url.username().is_empty() => percent_decode_str(url.username()).decode_utf8_lossy().to_string(),
+            Some(user) => user,
+            None => std::env::var("USER").or_else(|_| std::env::var("USERNAME"))
+                .map_err(|_| anyhow!("No SSH user configured; set ssh_user in the config"))?,
+        };
         
         let raw_path = url.path().to_string();
         let root_path = raw_path.replace('\\', "/");
 
+        let mut follow_symlinks = false;
+        let mut max_depth = None;
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "follow_symlinks" => follow_symlinks = matches!(value.as_ref(), "1" | "true" | "yes"),
+                "max_depth" => max_depth = Some(value.parse().context("max_depth must be a number")?),
+                other => return Err(anyhow!("Unknown SFTP option: {}", other)),
+            }
+        }
+
         let config = Arc::new(client::Config::default());
-        let sh = ClientHandler;
+        let sh = ClientHandler {
+            host: host.to_string(),
+            port,
+            known_hosts: options.known_hosts.take().unwrap_or_else(default_known_hosts),
+        };
 
         let mut session = client::connect(config, (host, port), sh).await
             .context("SSH Connection failed")?;
 
-        if !user.is_empty() && !session.authenticate_password(&user, &pass).await? {
-             return Err(anyhow!("Authentication failed for user: {}", user));
-        }
+        authenticate(&mut session, &user, &options).await?;
 
         let channel = session.channel_open_session().await.context("Failed to open SSH channel")?;
         channel.request_subsystem(true, "sftp").await.context("Failed to request SFTP subsystem")?;
@@ -86,43 +249,105 @@ impl SftpBackend {
         Ok(Self {
             sftp,
             root_path,
+            follow_symlinks,
+            max_depth,
         })
     }
+
+    fn resolve(&self, path: &str) -> String {
+        let clean = path.replace('\\', "/");
+        if self.root_path.ends_with('/') {
+            format!("{}{}", self.root_path, clean)
+        } else {
+            format!("{}/{}", self.root_path, clean)
+        }
+    }
+
+    async fn ensure_parent_dirs(&self, target: &str) {
+        let parts: Vec<&str> = target.split('/').collect();
+        let mut cur = String::new();
+
+        for (i, part) in parts.iter().enumerate() {
+            if i == parts.len() - 1 { break; }
+            
+            if part.is_empty() {
+                if i == 0 { cur.push('/'); }
+                continue;
+            }
+            
+            if !cur.ends_with('/') && !cur.is_empty() {
+                cur.push('/');
+            }
+            cur.push_str(part);
+
+            if cur != "/" && self.sftp.metadata(&cur).await.is_err() {
+                let _ = self.sftp.create_dir(&cur).await;
+            }
+        }
+    }
 }
 
 #[async_trait]
 impl StorageBackend for SftpBackend {
     async fn list_files(&self) -> Result<Vec<FileMetadata>> {
-        let paths = self.sftp.read_dir(&self.root_path).await.context("Failed to list remote directory")?;
         let mut files = Vec::new();
+        let mut visited = HashSet::new();
+        if self.follow_symlinks {
+            visited.insert(self.sftp.canonicalize(self.root_path.as_str()).await.context("Failed to resolve remote root")?);
+        }
+        let mut pending = vec![(self.root_path.clone(), String::new(), 0usize)];
 
-        for file in paths {
-            let name = file.file_name();
-            if name == "." || name == ".." { continue; }
+        while let Some((dir, prefix, depth)) = pending.pop() {
+            let entries = match self.sftp.read_dir(dir.as_str()).await {
+                Ok(entries) => entries,
+                Err(e) if depth == 0 => return Err(e).context("Failed to list remote directory"),
+                Err(e) => {
+                    eprintln!("[!] Failed to list {}: {}", dir, e);
+                    continue;
+                }
+            };
 
-            let meta = file.metadata();
-            if !meta.is_regular() { continue; }
+            for entry in entries {
+                let name = entry.file_name();
+                if name == "." || name == ".." { continue; }
 
-            files.push(FileMetadata {
-                path: name,
-                size: meta.size.unwrap_or(0), 
-                modified: meta.mtime.unwrap_or(0) as u64,
-                version: 0,
-                hash: String::new(),
-                is_deleted: false,
-                last_modified_by: None,
-            });
+                let rel = format!("{}{}", prefix, name);
+                let full = format!("{}/{}", dir.trim_end_matches('/'), name);
+
+                let mut meta = entry.metadata();
+                if meta.file_type().is_symlink() {
+                    if !self.follow_symlinks { continue; }
+                    match self.sftp.metadata(full.as_str()).await {
+                        Ok(target) => meta = target,
+                        Err(_) => continue,
+                    }
+                }
+
+                match meta.file_type() {
+                    FileType::File => files.push(file_entry(rel, &meta)),
+                    FileType::Dir if self.max_depth.is_none_or(|max| depth < max) => {
+                        if self.follow_symlinks {
+                            let Ok(real) = self.sftp.canonicalize(full.as_str()).await else { continue };
+                            if !visited.insert(real) { continue; }
+                        }
+                        pending.push((full, format!("{}/", rel), depth + 1));
+                    }
+                    _ => {}
+                }
+            }
         }
         Ok(files)
     }
 
+    async fn stat(&self, path: &str) -> Result<Option<FileMetadata>> {
+        match self.sftp.metadata(self.resolve(path)).await {
+            Ok(meta) if meta.file_type().is_file() => Ok(Some(file_entry(path.to_string(), &meta))),
+            _ => Ok(None),
+        }
+    }
+
     async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
-        let clean = path.replace('\\', "/");
-        let target = if self.root_path.ends_with('/') {
-            format!("{}{}", self.root_path, clean)
-        } else {
-            format!("{}/{}", self.root_path, clean)
-        };
+        let target = self.resolve(path);
 
         let mut file = self.sftp.open(&target).await.context(format!("Failed to open {} for reading", target))?;
         let size = file.metadata().await?.size.unwrap_or(0);
@@ -133,36 +358,20 @@ impl StorageBackend for SftpBackend {
         Ok(buf)
     }
 
-    async fn write_file(&self, path: &str, content: &[u8]) -> Result<()> {
-        let clean = path.replace('\\', "/");
-        let target = if self.root_path.ends_with('/') {
-            format!("{}{}", self.root_path, clean)
-        } else {
-            format!("{}/{}", self.root_path, clean)
-        };
+    async fn read_range(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let target = self.resolve(path);
 
-        let parts: Vec<&str> = target.split('/').collect();
-        let mut cur = String::new();
+        let mut file = self.sftp.open(&target).await.context(format!("Failed to open {} for reading", target))?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
 
-        for (i, part) in parts.iter().enumerate() {
-            if i == parts.len() - 1 { break; }
-            
-            if part.is_empty() {
-                if i == 0 { cur.push('/'); }
-                continue;
-            }
-            
-            if !cur.ends_with('/') && !cur.is_empty() {
-                cur.push('/');
-            }
-            cur.push_str(part);
+        let mut buf = Vec::with_capacity(len);
+        file.take(len as u64).read_to_end(&mut buf).await?;
+        Ok(buf)
+    }
 
-            if cur != "/" {
-                if self.sftp.metadata(&cur).await.is_err() {
-                    let _ = self.sftp.create_dir(&cur).await;
-                }
-            }
-        }
+    async fn write_file(&self, path: &str, content: &[u8]) -> Result<()> {
+        let target = self.resolve(path);
+        self.ensure_parent_dirs(&target).await;
 
         let mut file = self.sftp.create(&target).await
             .context(format!("SFTP 'create' failed for target: {}. Check folder permissions.", target))?;
@@ -174,17 +383,27 @@ impl StorageBackend for SftpBackend {
         Ok(())
     }
 
+    async fn write_from(&self, path: &str, source: &Path) -> Result<()> {
+        let target = self.resolve(path);
+        self.ensure_parent_dirs(&target).await;
+
+        let mut local = tokio::fs::File::open(source).await.context("Failed to open staged download")?;
+        let mut file = self.sftp.create(&target).await
+            .context(format!("SFTP 'create' failed for target: {}. Check folder permissions.", target))?;
+
+        tokio::io::copy(&mut local, &mut file).await.context("Failed to write content to remote file")?;
+        file.flush().await?;
+        file.shutdown().await?;
+
+        Ok(())
+    }
+
     async fn delete_file(&self, path: &str) -> Result<()> {
-        let clean = path.replace('\\', "/");
-        let target = if self.root_path.ends_with('/') {
-            format!("{}{}", self.root_path, clean)
-        } else {
-            format!("{}/{}", self.root_path, clean)
-        };
+        let target = self.resolve(path);
 
         if self.sftp.metadata(&target).await.is_ok() {
             self.sftp.remove_file(&target).await.context("Failed to delete remote file")?;
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
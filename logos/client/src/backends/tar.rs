@@ -0,0 +1,137 @@
+use crate::backend::StorageBackend;
+use crate::backends::cursor::ReadCursor;
+use common::FileMetadata;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tar::Archive;
+
+#[derive(Clone, Copy)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+pub struct TarBackend {
+    path: PathBuf,
+    compression: Compression,
+    cursor: Arc<ReadCursor>,
+}
+
+fn entry_path(raw: &std::path::Path) -> String {
+    let path = raw.to_string_lossy().replace("\\", "/");
+    path.trim_start_matches("./").to_string()
+}
+
+fn open(path: &PathBuf, compression: Compression) -> Result<Archive<Box<dyn Read + Send>>> {
+    let file = BufReader::new(File::open(path)?);
+    let reader: Box<dyn Read + Send> = match compression {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(flate2::read::GzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+    };
+    Ok(Archive::new(reader))
+}
+
+fn open_entry(path: &PathBuf, compression: Compression, target: &str) -> Result<Box<dyn Read + Send>> {
+    let mut archive = open(path, compression)?;
+    let mut size = None;
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_file() && entry_path(&entry.path()?) == target {
+            size = Some(entry.size());
+            break;
+        }
+    }
+    let size = size.ok_or_else(|| anyhow!("File not found"))?;
+    Ok(Box::new(archive.into_inner().take(size)))
+}
+
+impl TarBackend {
+    pub fn new(path: PathBuf) -> Result<Self> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+        let compression = if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Compression::Gzip
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Compression::Zstd
+        } else if name.ends_with(".tar") {
+            Compression::None
+        } else {
+            return Err(anyhow!("Unsupported archive {}, expected .tar, .tar.gz or .tar.zst", name));
+        };
+        open(&path, compression)?;
+        Ok(Self { path, compression, cursor: Arc::new(ReadCursor::default()) })
+    }
+}
+
+#[async_trait]
+impl StorageBackend for TarBackend {
+    fn is_read_only(&self) -> bool { true }
+
+    async fn list_files(&self) -> Result<Vec<FileMetadata>> {
+        let path = self.path.clone();
+        let compression = self.compression;
+        self.cursor.reset();
+        tokio::task::spawn_blocking(move || {
+            let mut archive = open(&path, compression)?;
+            let mut list = Vec::new();
+
+            for entry in archive.entries()? {
+                let entry = entry?;
+                let header = entry.header();
+                if !header.entry_type().is_file() { continue; }
+                list.push(FileMetadata {
+                    path: entry_path(&entry.path()?),
+                    size: entry.size(),
+                    modified: header.mtime().unwrap_or(0),
+                    version: 0,
+                    hash: String::new(),
+                    is_deleted: false,
+                    last_modified_by: None,
+                    chunks: Vec::new(),
+                });
+            }
+            Ok(list)
+        }).await?
+    }
+
+    async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        let archive_path = self.path.clone();
+        let compression = self.compression;
+        let p = path.to_string();
+        tokio::task::spawn_blocking(move || {
+            let mut archive = open(&archive_path, compression)?;
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if entry.header().entry_type().is_file() && entry_path(&entry.path()?) == p {
+                    let mut buf = Vec::with_capacity(entry.size() as usize);
+                    entry.read_to_end(&mut buf)?;
+                    return Ok(buf);
+                }
+            }
+            Err(anyhow!("File not found"))
+        }).await?
+    }
+
+    async fn read_range(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let archive_path = self.path.clone();
+        let compression = self.compression;
+        let cursor = self.cursor.clone();
+        let p = path.to_string();
+        tokio::task::spawn_blocking(move || {
+            cursor.read(&p, offset, len, || open_entry(&archive_path, compression, &p))
+        }).await?
+    }
+
+    async fn write_file(&self, _: &str, _: &[u8]) -> Result<()> {
+        Err(anyhow!("Tar archives are read-only"))
+    }
+
+    async fn delete_file(&self, _: &str) -> Result<()> {
+        Err(anyhow!("Tar archives are read-only"))
+    }
+}
@@ -0,0 +1,297 @@
+use crate::backend::StorageBackend;
+use crate::backends::etag::EtagTracker;
+use common::FileMetadata;
+use anyhow::{Result, Context, anyhow};
+use async_trait::async_trait;
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Mutex;
+use url::Url;
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:getcontentlength/><d:getlastmodified/><d:getetag/></d:prop></d:propfind>"#;
+
+const SEGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>')
+    .add(b'?').add(b'[').add(b']').add(b'^').add(b'`').add(b'{').add(b'|').add(b'}');
+
+#[derive(Default)]
+pub struct WebDavOptions {
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
+
+struct Entry {
+    path: String,
+    is_dir: bool,
+    size: u64,
+    modified: u64,
+    etag: Option<String>,
+}
+
+pub struct WebDavBackend {
+    client: Client,
+    base: Url,
+    user: Option<String>,
+    password: Option<String>,
+    etags: EtagTracker,
+    known_dirs: Mutex<HashSet<String>>,
+}
+
+fn parent_dirs(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/').map(move |(i, _)| &path[..i])
+}
+
+fn encode_path(path: &str) -> String {
+    path.split('/').map(|s| utf8_percent_encode(s, SEGMENT).to_string()).collect::<Vec<_>>().join("/")
+}
+
+fn file_entry(path: String, size: u64, modified: u64) -> FileMetadata {
+    FileMetadata {
+        path,
+        size,
+        modified,
+        version: 0,
+        hash: String::new(),
+        is_deleted: false,
+        last_modified_by: None,
+        chunks: Vec::new(),
+    }
+}
+
+fn check(response: reqwest::Response, action: &str, path: &str) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(response)
+    } else {
+        Err(anyhow!("{} {} failed: {}", action, path, status))
+    }
+}
+
+impl WebDavBackend {
+    pub async fn new(url: &Url, options: WebDavOptions) -> Result<Self> {
+        let scheme = match url.scheme() {
+            "webdav" => "http",
+            "webdavs" => "https",
+            _ => return Err(anyhow!("Scheme must be webdav or webdavs")),
+        };
+        let host = url.host_str().context("No host provided")?;
+        let mut base = Url::parse(&format!("{}://{}", scheme, host))?;
+        base.set_port(url.port()).map_err(|_| anyhow!("Invalid port"))?;
+        base.set_path(&format!("{}/", url.path().trim_end_matches('/')));
+
+        let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().to_string();
+        let user = options.user.or_else(|| (!url.username().is_empty()).then(|| decode(url.username())));
+        let password = options.password.or_else(|| url.password().map(decode));
+
+        let backend = Self {
+            client: Client::builder().build().context("Failed to build HTTP client")?,
+            base,
+            user,
+            password,
+            etags: EtagTracker::default(),
+            known_dirs: Mutex::new(HashSet::new()),
+        };
+
+        if backend.propfind("", "0").await?.is_none() {
+            println!("[*] Remote root '{}' missing, attempting to create...", backend.base.path());
+            let root = backend.base.path().trim_matches('/').to_string();
+            let mut cur = String::from("/");
+            for part in root.split('/') {
+                cur.push_str(part);
+                cur.push('/');
+                let mut dir = backend.base.clone();
+                dir.set_path(&cur);
+                let _ = backend.request(Method::from_bytes(b"MKCOL")?, dir).send().await;
+            }
+            backend.propfind("", "0").await?.context("Failed to create remote root")?;
+        }
+        Ok(backend)
+    }
+
+    fn url(&self, path: &str) -> Result<Url> {
+        self.base.join(&encode_path(path)).context("Invalid path")
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        match &self.user {
+            Some(user) => request.basic_auth(user, self.password.as_ref()),
+            None => request,
+        }
+    }
+
+    async fn propfind(&self, dir: &str, depth: &str) -> Result<Option<Vec<Entry>>> {
+        let url = self.url(dir)?;
+        let response = self.request(Method::from_bytes(b"PROPFIND")?, url)
+            .header("Depth", depth)
+            .header("Content-Type", "application/xml")
+            .body(PROPFIND_BODY)
+            .send().await
+            .with_context(|| format!("PROPFIND {} failed", dir))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body = check(response, "PROPFIND", if dir.is_empty() { "/" } else { dir })?.text().await?;
+        self.parse_multistatus(&body).map(Some)
+    }
+
+    fn parse_multistatus(&self, body: &str) -> Result<Vec<Entry>> {
+        let doc = roxmltree::Document::parse(body).context("Invalid PROPFIND response")?;
+        let base_path = percent_decode_str(self.base.path()).decode_utf8_lossy().to_string();
+        let dav = |node: &roxmltree::Node, name: &str| node.tag_name().namespace() == Some("DAV:") && node.tag_name().name() == name;
+        let mut entries = Vec::new();
+
+        for response in doc.descendants().filter(|n| dav(n, "response")) {
+            let Some(href) = response.descendants().find(|n| dav(n, "href")).and_then(|n| n.text()) else { continue };
+            let href_path = self.base.join(href.trim()).map(|u| u.path().to_string()).unwrap_or_default();
+            let decoded = percent_decode_str(&href_path).decode_utf8_lossy().to_string();
+            let Some(rel) = decoded.strip_prefix(&base_path).or_else(|| (decoded.trim_end_matches('/') == base_path.trim_end_matches('/')).then_some("")) else { continue };
+
+            let ok_props = response.descendants()
+                .filter(|n| dav(n, "propstat"))
+                .filter(|n| n.descendants().find(|s| dav(s, "status")).and_then(|s| s.text()).is_none_or(|s| s.contains(" 200 ")))
+                .flat_map(|n| n.descendants().filter(|p| dav(p, "prop")).collect::<Vec<_>>());
+
+            let mut entry = Entry { path: rel.trim_end_matches('/').to_string(), is_dir: false, size: 0, modified: 0, etag: None };
+            for prop in ok_props {
+                for child in prop.children().filter(|c| c.is_element()) {
+                    let text = child.text().unwrap_or("").trim();
+                    match child.tag_name().name() {
+                        "resourcetype" => entry.is_dir = child.children().any(|c| dav(&c, "collection")),
+                        "getcontentlength" => entry.size = text.parse().unwrap_or(0),
+                        "getlastmodified" => entry.modified = chrono::DateTime::parse_from_rfc2822(text)
+                            .map(|t| t.timestamp().max(0) as u64).unwrap_or(0),
+                        "getetag" if !text.is_empty() => entry.etag = Some(text.to_string()),
+                        _ => {}
+                    }
+                }
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    async fn ensure_parent_dirs(&self, path: &str) -> Result<()> {
+        for dir in parent_dirs(path) {
+            if self.known_dirs.lock().is_ok_and(|dirs| dirs.contains(dir)) { continue; }
+            let response = self.request(Method::from_bytes(b"MKCOL")?, self.url(&format!("{}/", dir))?).send().await?;
+            let status = response.status();
+            if !status.is_success() && status != StatusCode::METHOD_NOT_ALLOWED {
+                return Err(anyhow!("MKCOL {} failed: {}", dir, status));
+            }
+            if let Ok(mut dirs) = self.known_dirs.lock() {
+                dirs.insert(dir.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    async fn refresh(&self, path: &str) {
+        if let Ok(Some(entries)) = self.propfind(path, "0").await {
+            for entry in entries.iter().filter(|e| !e.is_dir) {
+                self.etags.observe(&entry.path, entry.etag.as_deref(), entry.modified);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for WebDavBackend {
+    async fn list_files(&self) -> Result<Vec<FileMetadata>> {
+        let mut files = Vec::new();
+        let mut pending = vec![String::new()];
+
+        while let Some(dir) = pending.pop() {
+            let entries = match self.propfind(&dir, "1").await {
+                Ok(entries) => entries.unwrap_or_default(),
+                Err(e) if dir.is_empty() => return Err(e),
+                Err(e) => {
+                    eprintln!("[!] Failed to list {}: {}", dir, e);
+                    continue;
+                }
+            };
+            for entry in entries {
+                if entry.path == dir || entry.path.is_empty() { continue; }
+                if entry.is_dir {
+                    if let Ok(mut dirs) = self.known_dirs.lock() {
+                        dirs.insert(entry.path.clone());
+                    }
+                    pending.push(entry.path);
+                } else {
+                    let modified = self.etags.observe(&entry.path, entry.etag.as_deref(), entry.modified);
+                    files.push(file_entry(entry.path, entry.size, modified));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    async fn stat(&self, path: &str) -> Result<Option<FileMetadata>> {
+        let entries = self.propfind(path, "0").await?.unwrap_or_default();
+        Ok(entries.into_iter().find(|e| !e.is_dir).map(|entry| {
+            let modified = self.etags.observe(&entry.path, entry.etag.as_deref(), entry.modified);
+            file_entry(path.to_string(), entry.size, modified)
+        }))
+    }
+
+    async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        let response = self.request(Method::GET, self.url(path)?).send().await?;
+        Ok(check(response, "GET", path)?.bytes().await?.to_vec())
+    }
+
+    async fn read_range(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let mut request = self.request(Method::GET, self.url(path)?)
+            .header("Range", format!("bytes={}-{}", offset, offset + len as u64 - 1));
+        if let Some(etag) = self.etags.etag(path) {
+            request = request.header("If-Match", etag);
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            return Ok(Vec::new());
+        }
+        let response = check(response, "GET", path)?;
+        let partial = response.status() == StatusCode::PARTIAL_CONTENT;
+        let body = response.bytes().await?;
+        if partial {
+            return Ok(body.to_vec());
+        }
+        let start = (offset as usize).min(body.len());
+        let end = start.saturating_add(len).min(body.len());
+        Ok(body[start..end].to_vec())
+    }
+
+    async fn write_file(&self, path: &str, content: &[u8]) -> Result<()> {
+        self.ensure_parent_dirs(path).await?;
+        let response = self.request(Method::PUT, self.url(path)?).body(content.to_vec()).send().await?;
+        check(response, "PUT", path)?;
+        self.refresh(path).await;
+        Ok(())
+    }
+
+    async fn write_from(&self, path: &str, source: &Path) -> Result<()> {
+        self.ensure_parent_dirs(path).await?;
+        let file = tokio::fs::File::open(source).await?;
+        let size = file.metadata().await?.len();
+        let response = self.request(Method::PUT, self.url(path)?)
+            .header("Content-Length", size)
+            .body(file)
+            .send().await?;
+        check(response, "PUT", path)?;
+        self.refresh(path).await;
+        Ok(())
+    }
+
+    async fn delete_file(&self, path: &str) -> Result<()> {
+        let response = self.request(Method::DELETE, self.url(path)?).send().await?;
+        if response.status() != StatusCode::NOT_FOUND {
+            check(response, "DELETE", path)?;
+        }
+        self.etags.forget(path);
+        Ok(())
+    }
+}
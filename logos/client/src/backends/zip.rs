@@ -1,54 +1,227 @@
 use crate::backend::StorageBackend;
+use crate::backends::cursor::ReadCursor;
 use common::FileMetadata;
 use anyhow::{Result, anyhow, Context};
 use async_trait::async_trait;
+use chrono::{Datelike, Local, NaiveDate, TimeZone, Timelike};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use zip::ZipArchive;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+const FLUSH_DELAY: Duration = Duration::from_millis(250);
+
+enum Change {
+    Write { content: Vec<u8>, modified: DateTime },
+    Delete,
+}
+
+struct ZipState {
+    archive: Option<ZipArchive<File>>,
+    pending: HashMap<String, Change>,
+    waiters: Vec<oneshot::Sender<Result<(), String>>>,
+    flush_scheduled: bool,
+}
 
 pub struct ZipBackend {
-    zip: Arc<Mutex<ZipArchive<File>>>,
+    path: PathBuf,
+    writable: bool,
+    state: Arc<Mutex<ZipState>>,
+    cursor: Arc<ReadCursor>,
+}
+
+fn extended_mtime(extra: &[u8]) -> Option<u64> {
+    let mut rest = extra;
+    while rest.len() >= 4 {
+        let id = u16::from_le_bytes([rest[0], rest[1]]);
+        let len = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        let data = rest.get(4..4 + len)?;
+        if id == 0x5455 && data.len() >= 5 && data[0] & 1 != 0 {
+            return Some(i32::from_le_bytes(data[1..5].try_into().ok()?).max(0) as u64);
+        }
+        rest = &rest[4 + len..];
+    }
+    None
+}
+
+fn timestamp(dt: &DateTime) -> u64 {
+    NaiveDate::from_ymd_opt(dt.year() as i32, dt.month() as u32, dt.day() as u32)
+        .and_then(|d| d.and_hms_opt(dt.hour() as u32, dt.minute() as u32, dt.second() as u32))
+        .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+        .map(|t| t.timestamp().max(0) as u64)
+        .unwrap_or(0)
+}
+
+fn now() -> DateTime {
+    let t = Local::now();
+    DateTime::from_date_and_time(t.year() as u16, t.month() as u8, t.day() as u8, t.hour() as u8, t.minute() as u8, (t.second() & !1) as u8)
+        .unwrap_or_default()
+}
+
+fn entry(path: String, size: u64, modified: u64) -> FileMetadata {
+    FileMetadata {
+        path,
+        size,
+        modified,
+        version: 0,
+        hash: String::new(),
+        is_deleted: false,
+        last_modified_by: None,
+        chunks: Vec::new(),
+    }
+}
+
+fn open_entry(path: &Path, archive: &mut ZipArchive<File>, target: &str) -> Result<Box<dyn Read + Send>> {
+    let win_target = target.replace("/", "\\");
+    let name = if archive.by_name(target).is_ok() { target } else { win_target.as_str() };
+    let (start, size, method) = {
+        let f = archive.by_name(name).context("File not found")?;
+        (f.data_start(), f.compressed_size(), f.compression())
+    };
+
+    let mut raw = File::open(path)?;
+    raw.seek(SeekFrom::Start(start))?;
+    let raw = BufReader::new(raw).take(size);
+    Ok(match method {
+        CompressionMethod::Stored => Box::new(raw),
+        CompressionMethod::Deflated => Box::new(flate2::read::DeflateDecoder::new(raw)),
+        _ => {
+            let mut buf = Vec::new();
+            archive.by_name(name)?.read_to_end(&mut buf)?;
+            Box::new(std::io::Cursor::new(buf))
+        }
+    })
+}
+
+fn rewrite(path: &Path, archive: &mut Option<ZipArchive<File>>, pending: &HashMap<String, Change>) -> Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.tmp", name));
+
+    let result = (|| -> Result<()> {
+        let mut writer = ZipWriter::new(File::create(&tmp)?);
+        if let Some(archive) = archive.as_mut() {
+            for i in 0..archive.len() {
+                let file = archive.by_index_raw(i)?;
+                if pending.contains_key(&file.name().replace("\\", "/")) { continue; }
+                writer.raw_copy_file(file)?;
+            }
+        }
+        for (name, change) in pending {
+            if let Change::Write { content, modified } = change {
+                let options = FileOptions::default()
+                    .compression_method(CompressionMethod::Deflated)
+                    .last_modified_time(*modified)
+                    .large_file(content.len() as u64 >= u32::MAX as u64);
+                writer.start_file(name, options)?;
+                writer.write_all(content)?;
+            }
+        }
+        writer.finish()?.sync_all()?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+
+    *archive = None;
+    let renamed = std::fs::rename(&tmp, path);
+    if path.exists() {
+        *archive = Some(ZipArchive::new(File::open(path)?)?);
+    }
+    renamed.with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+fn flush(path: &Path, state: &Mutex<ZipState>, cursor: &ReadCursor) {
+    let Ok(mut state) = state.lock() else { return };
+    let state = &mut *state;
+    state.flush_scheduled = false;
+    let pending = std::mem::take(&mut state.pending);
+    let waiters = std::mem::take(&mut state.waiters);
+
+    let result = rewrite(path, &mut state.archive, &pending)
+        .with_context(|| format!("Failed to write {}", path.display()));
+    cursor.reset();
+    match &result {
+        Ok(()) => println!("[+] Wrote {} changes to {}", pending.len(), path.display()),
+        Err(e) => eprintln!("[!] {:#}", e),
+    }
+    for waiter in waiters {
+        let _ = waiter.send(result.as_ref().map(|_| ()).map_err(|e| format!("{:#}", e)));
+    }
 }
 
 impl ZipBackend {
-    pub fn new(path: PathBuf) -> Result<Self> {
-        let f = File::open(&path)?;
-        let z = ZipArchive::new(f)?;
+    pub fn new(path: PathBuf, writable: bool) -> Result<Self> {
+        let archive = if writable && !path.exists() {
+            None
+        } else {
+            let f = File::open(&path)?;
+            Some(ZipArchive::new(f)?)
+        };
         Ok(Self {
-            zip: Arc::new(Mutex::new(z)),
+            path,
+            writable,
+            state: Arc::new(Mutex::new(ZipState {
+                archive,
+                pending: HashMap::new(),
+                waiters: Vec::new(),
+                flush_scheduled: false,
+            })),
+            cursor: Arc::new(ReadCursor::default()),
         })
     }
+
+    async fn stage(&self, path: &str, change: Change) -> Result<()> {
+        if !self.writable {
+            return Err(anyhow!("Zip is read-only"));
+        }
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = self.state.lock().map_err(|_| anyhow!("Zip mutex poisoned"))?;
+            state.pending.insert(path.to_string(), change);
+            state.waiters.push(tx);
+            if !state.flush_scheduled {
+                state.flush_scheduled = true;
+                let shared = self.state.clone();
+                let cursor = self.cursor.clone();
+                let archive_path = self.path.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(FLUSH_DELAY).await;
+                    if let Err(e) = tokio::task::spawn_blocking(move || flush(&archive_path, &shared, &cursor)).await {
+                        eprintln!("[!] Archive writer crashed: {}", e);
+                    }
+                });
+            }
+        }
+        rx.await
+            .map_err(|_| anyhow!("Archive writer stopped before {} was written", path))?
+            .map_err(|e| anyhow!(e))
+    }
 }
 
 #[async_trait]
 impl StorageBackend for ZipBackend {
-    fn is_read_only(&self) -> bool { true }
+    fn is_read_only(&self) -> bool { !self.writable }
 
     async fn list_files(&self) -> Result<Vec<FileMetadata>> {
-        let z = self.zip.clone();
+        let z = self.state.clone();
         tokio::task::spawn_blocking(move || {
-            let mut archive = z.lock().map_err(|_| anyhow!("Zip mutex poisoned"))?;
+            let mut state = z.lock().map_err(|_| anyhow!("Zip mutex poisoned"))?;
             let mut list = Vec::new();
-            
-            for i in 0..archive.len() {
-                if let Ok(f) = archive.by_index(i) && f.is_file() {
-                    let name = f.name().replace("\\", "/");
-                    let dt = f.last_modified();
-                    let ts = (dt.year() as u64).saturating_sub(1970) * 31536000 
-                            + (dt.month() as u64) * 2592000 
-                            + (dt.day() as u64) * 86400;
-
-                    list.push(FileMetadata {
-                        path: name,
-                        size: f.size(),
-                        modified: ts, 
-                        version: 0,
-                        hash: String::new(),
-                        is_deleted: false,
-                        last_modified_by: None,
-                    });
+
+            if let Some(archive) = state.archive.as_mut() {
+                for i in 0..archive.len() {
+                    if let Ok(f) = archive.by_index(i) && f.is_file() {
+                        let name = f.name().replace("\\", "/");
+                        let modified = extended_mtime(f.extra_data()).unwrap_or_else(|| timestamp(&f.last_modified()));
+                        list.push(entry(name, f.size(), modified));
+                    }
                 }
             }
             Ok(list)
@@ -56,18 +229,19 @@ impl StorageBackend for ZipBackend {
     }
 
     async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
-        let z = self.zip.clone();
+        let z = self.state.clone();
         let p = path.to_string();
-        
+
         tokio::task::spawn_blocking(move || {
-            let mut archive = z.lock().map_err(|_| anyhow!("Zip mutex poisoned"))?;
+            let mut state = z.lock().map_err(|_| anyhow!("Zip mutex poisoned"))?;
+            let archive = state.archive.as_mut().context("File not found")?;
             let mut buf = Vec::new();
-            
+
             if let Ok(mut f) = archive.by_name(&p) {
                 f.read_to_end(&mut buf)?;
                 return Ok(buf);
             }
-            
+
             let win_p = p.replace("/", "\\");
             let mut f = archive.by_name(&win_p).context("File not found")?;
             f.read_to_end(&mut buf)?;
@@ -75,11 +249,26 @@ impl StorageBackend for ZipBackend {
         }).await?
     }
 
-    async fn write_file(&self, _: &str, _: &[u8]) -> Result<()> {
-        Err(anyhow!("Zip is read-only"))
+    async fn read_range(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let z = self.state.clone();
+        let archive_path = self.path.clone();
+        let cursor = self.cursor.clone();
+        let p = path.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            cursor.read(&p, offset, len, || {
+                let mut state = z.lock().map_err(|_| anyhow!("Zip mutex poisoned"))?;
+                let archive = state.archive.as_mut().context("File not found")?;
+                open_entry(&archive_path, archive, &p)
+            })
+        }).await?
+    }
+
+    async fn write_file(&self, path: &str, content: &[u8]) -> Result<()> {
+        self.stage(path, Change::Write { content: content.to_vec(), modified: now() }).await
     }
 
-    async fn delete_file(&self, _: &str) -> Result<()> {
-        Err(anyhow!("Zip is read-only"))
+    async fn delete_file(&self, path: &str) -> Result<()> {
+        self.stage(path, Change::Delete).await
     }
-}
\ No newline at end of file
+}
@@ -1,21 +1,58 @@
+use common::Subscription;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::Path;
 use tokio::fs;
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairConfig {
+    pub location: String,
+    pub storage_id: Option<String>,
+    #[serde(flatten)]
+    pub subscription: Subscription,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct AppConfig {
     pub client_name: Option<String>,
+    pub device_id: Option<String>,
+    pub server_url: Option<String>,
+    pub ca_cert: Option<String>,
+    pub pinned_cert: Option<String>,
+    pub known_hosts: Option<String>,
+    pub ssh_user: Option<String>,
+    pub ssh_password: Option<String>,
+    pub ssh_key: Option<String>,
+    pub s3_endpoint: Option<String>,
+    pub s3_region: Option<String>,
+    pub s3_access_key: Option<String>,
+    pub s3_secret_key: Option<String>,
+    pub s3_path_style: Option<bool>,
+    pub webdav_user: Option<String>,
+    pub webdav_password: Option<String>,
+    #[serde(default)]
+    pub pairs: Vec<PairConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub storage_id: Option<String>,
+    pub token: Option<String>,
+    #[serde(default)]
+    pub passphrases: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passphrase: Option<String>,
 }
 
 impl AppConfig {
     pub async fn load(path: &str) -> Self {
         if Path::new(path).exists() {
             match fs::read_to_string(path).await {
-                Ok(content) => match serde_json::from_str(&content) {
-                    Ok(cfg) => {
+                Ok(content) => match serde_json::from_str::<Self>(&content) {
+                    Ok(mut cfg) => {
                         println!("[+] Loaded configuration from {}", path);
+                        if let Some(location) = cfg.location.take() {
+                            cfg.pairs.insert(0, PairConfig { location, storage_id: cfg.storage_id.take(), subscription: Subscription::default() });
+                        }
                         return cfg;
                     },
                     Err(e) => eprintln!("[!] Failed to parse config: {}", e),
@@ -0,0 +1,97 @@
+use anyhow::{Context, Result, anyhow};
+use native_tls::{Certificate, TlsConnector};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream, connect_async_tls_with_config};
+use url::Url;
+
+pub const DEFAULT_SERVER_URL: &str = "ws://localhost:3000/ws/client";
+
+pub type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub struct Endpoint {
+    url: Url,
+    ca_bundle: Option<Vec<Certificate>>,
+    pinned: Option<String>,
+}
+
+fn normalize_fingerprint(raw: &str) -> Result<String> {
+    let hex: String = raw.chars().filter(|c| *c != ':').collect::<String>().to_lowercase();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Pinned certificate must be a SHA-256 fingerprint, got {}", raw));
+    }
+    Ok(hex)
+}
+
+pub fn load_ca_bundle(path: &str) -> Result<Vec<Certificate>> {
+    let pem = std::fs::read_to_string(path).with_context(|| format!("Failed to read CA bundle {}", path))?;
+    let certs = pem.split_inclusive("-----END CERTIFICATE-----")
+        .filter(|block| block.contains("-----BEGIN CERTIFICATE-----"))
+        .map(|block| Certificate::from_pem(block.trim().as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid certificate in {}", path))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in {}", path));
+    }
+    Ok(certs)
+}
+
+impl Endpoint {
+    pub fn new(server_url: &str, ca_cert: Option<&str>, pinned_cert: Option<&str>) -> Result<Self> {
+        let mut url = Url::parse(server_url).with_context(|| format!("Invalid server URL {}", server_url))?;
+        match url.scheme() {
+            "ws" if ca_cert.is_some() || pinned_cert.is_some() => {
+                return Err(anyhow!("Custom CA and certificate pinning require a wss:// server URL"));
+            }
+            "ws" | "wss" => {}
+            other => return Err(anyhow!("Unsupported server URL scheme: {}", other)),
+        }
+        if url.path().is_empty() || url.path() == "/" {
+            url.set_path("/ws/client");
+        }
+
+        Ok(Self {
+            url,
+            ca_bundle: ca_cert.map(load_ca_bundle).transpose()?,
+            pinned: pinned_cert.map(normalize_fingerprint).transpose()?,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        self.url.as_str()
+    }
+
+    fn connector(&self) -> Result<Option<Connector>> {
+        if self.url.scheme() != "wss" {
+            return Ok(None);
+        }
+        let mut builder = TlsConnector::builder();
+        for cert in self.ca_bundle.iter().flatten() {
+            builder.add_root_certificate(cert.clone());
+        }
+        if self.pinned.is_some() && self.ca_bundle.is_none() {
+            builder.danger_accept_invalid_certs(true).danger_accept_invalid_hostnames(true);
+        }
+        Ok(Some(Connector::NativeTls(builder.build().context("Failed to build TLS connector")?)))
+    }
+
+    pub async fn connect(&self) -> Result<Socket> {
+        let (socket, _) = connect_async_tls_with_config(self.url.as_str(), None, false, self.connector()?).await?;
+        if let Some(pinned) = &self.pinned {
+            let actual = peer_fingerprint(&socket)?;
+            if &actual != pinned {
+                return Err(anyhow!("Server certificate fingerprint {} does not match pinned {}", actual, pinned));
+            }
+        }
+        Ok(socket)
+    }
+}
+
+fn peer_fingerprint(socket: &Socket) -> Result<String> {
+    let MaybeTlsStream::NativeTls(tls) = socket.get_ref() else {
+        return Err(anyhow!("Connection is not encrypted"));
+    };
+    let cert = tls.get_ref().peer_certificate()?
+        .ok_or_else(|| anyhow!("Server presented no certificate"))?;
+    Ok(hex::encode(Sha256::digest(cert.to_der()?)))
+}
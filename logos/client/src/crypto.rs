@@ -0,0 +1,179 @@
+use aes_gcm_siv::aead::{Aead, KeyInit};
+use aes_gcm_siv::{Aes256GcmSiv, Nonce};
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use common::{EncryptionInfo, FileMetadata, Message};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+const NONCE_LEN: usize = 12;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub struct Cipher {
+    content: Aes256GcmSiv,
+    nonce_key: [u8; 32],
+    paths: Option<Aes256GcmSiv>,
+    path_nonce_key: [u8; 32],
+}
+
+fn derive_master(passphrase: &str, salt: &str) -> Result<[u8; 32]> {
+    let salt = URL_SAFE_NO_PAD.decode(salt).map_err(|e| anyhow!("Invalid salt: {}", e))?;
+    let mut master = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut master)
+        .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+    Ok(master)
+}
+
+fn subkey(master: &[u8; 32], label: &str) -> [u8; 32] {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(master).expect("HMAC accepts any key length");
+    mac.update(label.as_bytes());
+    mac.finalize().into_bytes().into()
+}
+
+fn synthetic_nonce(key: &[u8; 32], data: &[u8]) -> [u8; NONCE_LEN] {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    let tag = mac.finalize().into_bytes();
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce.copy_from_slice(&tag[..NONCE_LEN]);
+    nonce
+}
+
+fn key_check(master: &[u8; 32]) -> String {
+    URL_SAFE_NO_PAD.encode(subkey(master, "logos-key-check"))
+}
+
+pub fn new_storage_encryption(passphrase: &str, encrypt_paths: bool) -> Result<EncryptionInfo> {
+    let salt = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 16]>());
+    let master = derive_master(passphrase, &salt)?;
+    Ok(EncryptionInfo { key_check: key_check(&master), salt, encrypt_paths })
+}
+
+impl Cipher {
+    pub fn unlock(passphrase: &str, info: &EncryptionInfo) -> Result<Self> {
+        let master = derive_master(passphrase, &info.salt)?;
+        if key_check(&master) != info.key_check {
+            return Err(anyhow!("Wrong passphrase for encrypted storage"));
+        }
+
+        let content = Aes256GcmSiv::new_from_slice(&subkey(&master, "logos-content"))
+            .map_err(|e| anyhow!("Invalid key: {}", e))?;
+        let paths = if info.encrypt_paths {
+            Some(Aes256GcmSiv::new_from_slice(&subkey(&master, "logos-path"))
+                .map_err(|e| anyhow!("Invalid key: {}", e))?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            content,
+            nonce_key: subkey(&master, "logos-nonce"),
+            paths,
+            path_nonce_key: subkey(&master, "logos-path-nonce"),
+        })
+    }
+
+    pub fn encrypts_paths(&self) -> bool {
+        self.paths.is_some()
+    }
+
+    pub fn seal(&self, plain: &[u8]) -> Result<Vec<u8>> {
+        let nonce = synthetic_nonce(&self.nonce_key, plain);
+        let sealed = self.content.encrypt(Nonce::from_slice(&nonce), plain)
+            .map_err(|_| anyhow!("Encryption failed"))?;
+        let mut out = Vec::with_capacity(NONCE_LEN + sealed.len());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        Ok(out)
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return Err(anyhow!("Encrypted chunk too short"));
+        }
+        let (nonce, body) = sealed.split_at(NONCE_LEN);
+        self.content.decrypt(Nonce::from_slice(nonce), body)
+            .map_err(|_| anyhow!("Decryption failed"))
+    }
+
+    fn map_path(&self, path: String, encrypt: bool) -> Result<String> {
+        let Some(cipher) = &self.paths else { return Ok(path) };
+        path.split('/')
+            .map(|part| {
+                if encrypt {
+                    let nonce = synthetic_nonce(&self.path_nonce_key, part.as_bytes());
+                    let sealed = cipher.encrypt(Nonce::from_slice(&nonce), part.as_bytes())
+                        .map_err(|_| anyhow!("Encryption failed"))?;
+                    Ok(URL_SAFE_NO_PAD.encode([&nonce[..], &sealed].concat()))
+                } else {
+                    let raw = URL_SAFE_NO_PAD.decode(part).map_err(|_| anyhow!("Unencrypted path component: {}", part))?;
+                    if raw.len() < NONCE_LEN {
+                        return Err(anyhow!("Encrypted path component too short"));
+                    }
+                    let (nonce, body) = raw.split_at(NONCE_LEN);
+                    let plain = cipher.decrypt(Nonce::from_slice(nonce), body)
+                        .map_err(|_| anyhow!("Failed to decrypt path component"))?;
+                    String::from_utf8(plain).map_err(|e| anyhow!("Invalid path: {}", e))
+                }
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(|parts| parts.join("/"))
+    }
+
+    pub fn seal_paths(&self, msg: Message) -> Result<Message> {
+        self.map_paths(msg, true)
+    }
+
+    pub fn open_paths(&self, msg: Message) -> Result<Message> {
+        self.map_paths(msg, false)
+    }
+
+    pub fn open_meta(&self, meta: FileMetadata) -> Result<FileMetadata> {
+        self.map_meta(meta, false)
+    }
+
+    fn map_meta(&self, mut meta: FileMetadata, encrypt: bool) -> Result<FileMetadata> {
+        meta.path = self.map_path(meta.path, encrypt)?;
+        Ok(meta)
+    }
+
+    fn map_paths(&self, msg: Message, encrypt: bool) -> Result<Message> {
+        if self.paths.is_none() {
+            return Ok(msg);
+        }
+        let path = |p: String| self.map_path(p, encrypt);
+
+        Ok(match msg {
+            Message::Welcome { storage_id, files, encryption, ignore } => Message::Welcome {
+                storage_id,
+                files: files.into_iter().map(|m| self.map_meta(m, encrypt)).collect::<Result<_>>()?,
+                encryption,
+                ignore,
+            },
+            Message::FileUpdate { meta } => Message::FileUpdate { meta: self.map_meta(meta, encrypt)? },
+            Message::StartTransfer { path: p, size, target_version, hash, chunks } => {
+                Message::StartTransfer { path: path(p)?, size, target_version, hash, chunks }
+            }
+            Message::RequestChunks { path: p, chunks } => Message::RequestChunks { path: path(p)?, chunks },
+            Message::TransferChunk { path: p, offset, hash } => Message::TransferChunk { path: path(p)?, offset, hash },
+            Message::RequestFile { path: p, offset } => Message::RequestFile { path: path(p)?, offset },
+            Message::DeleteFile { path: p } => Message::DeleteFile { path: path(p)? },
+            Message::ListVersions { path: p } => Message::ListVersions { path: path(p)? },
+            Message::VersionList { path: p, versions } => Message::VersionList {
+                path: path(p)?,
+                versions: versions.into_iter().map(|m| self.map_meta(m, encrypt)).collect::<Result<_>>()?,
+            },
+            Message::RestoreVersion { path: p, version } => Message::RestoreVersion { path: path(p)?, version },
+            Message::Synced { path: p, version } => Message::Synced { path: path(p)?, version },
+            Message::ConflictDetected { path: p, server_version, base } => Message::ConflictDetected {
+                path: path(p)?,
+                server_version,
+                base: base.map(|m| self.map_meta(m, encrypt)).transpose()?,
+            },
+            other => other,
+        })
+    }
+}
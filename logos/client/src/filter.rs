@@ -0,0 +1,193 @@
+use crate::backend::StorageBackend;
+use anyhow::Result;
+use async_trait::async_trait;
+use common::{FileMetadata, Subscription};
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, RwLock};
+
+pub const IGNORE_FILE: &str = ".logosignore";
+const DEFAULT_PATTERNS: &[&str] = &[".git/"];
+
+static DEFAULTS: LazyLock<Option<Gitignore>> = LazyLock::new(|| build("", DEFAULT_PATTERNS.iter().copied(), "default patterns"));
+
+struct IgnoreFile {
+    size: u64,
+    modified: u64,
+    matcher: Gitignore,
+}
+
+#[derive(Default)]
+struct Rules {
+    server: Option<Gitignore>,
+    subscription: Subscription,
+    files: HashMap<String, IgnoreFile>,
+}
+
+#[derive(Default)]
+pub struct IgnoreRules {
+    rules: RwLock<Rules>,
+}
+
+fn build<'a>(dir: &str, lines: impl Iterator<Item = &'a str>, origin: &str) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir);
+    for line in lines {
+        if let Err(e) = builder.add_line(None, line) {
+            eprintln!("[!] Invalid ignore pattern in {}: {}", origin, e);
+        }
+    }
+    builder.build().map_err(|e| eprintln!("[!] Failed to load ignore rules from {}: {}", origin, e)).ok()
+}
+
+fn ignore_dir(path: &str) -> Option<&str> {
+    match path.rsplit_once('/') {
+        Some((dir, IGNORE_FILE)) => Some(dir),
+        None if path == IGNORE_FILE => Some(""),
+        _ => None,
+    }
+}
+
+pub fn is_ignore_file(path: &str) -> bool {
+    ignore_dir(path).is_some()
+}
+
+impl IgnoreRules {
+    pub fn set_patterns(&self, patterns: &[String]) {
+        let matcher = if patterns.is_empty() {
+            None
+        } else {
+            build("", patterns.iter().map(String::as_str), "server patterns")
+        };
+        if let Ok(mut rules) = self.rules.write() {
+            rules.server = matcher;
+        }
+    }
+
+    pub fn set_subscription(&self, subscription: &Subscription) {
+        if let Ok(mut rules) = self.rules.write() {
+            rules.subscription = subscription.clone();
+        }
+    }
+
+    pub fn is_ignored(&self, path: &str) -> bool {
+        let Ok(rules) = self.rules.read() else { return false };
+        if !rules.subscription.covers(path) {
+            return true;
+        }
+        let target = Path::new(path);
+        let dirs = target.ancestors().skip(1).map(|dir| dir.to_string_lossy());
+        let matchers = dirs.filter_map(|dir| rules.files.get(dir.as_ref()).map(|f| &f.matcher));
+
+        for matcher in matchers.chain(rules.server.as_ref()).chain(DEFAULTS.as_ref()) {
+            match matcher.matched_path_or_any_parents(target, false) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+
+    pub fn filter(&self, files: Vec<FileMetadata>) -> Vec<FileMetadata> {
+        files.into_iter().filter(|f| !self.is_ignored(&f.path)).collect()
+    }
+
+    async fn load(&self, backend: &dyn StorageBackend, local: &FileMetadata) {
+        let Some(dir) = ignore_dir(&local.path) else { return };
+        let content = match backend.read_file(&local.path).await {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("[!] Failed to read {}: {}", local.path, e);
+                return;
+            }
+        };
+        let text = String::from_utf8_lossy(&content);
+        let Some(matcher) = build(dir, text.lines(), &local.path) else { return };
+        if let Ok(mut rules) = self.rules.write() {
+            rules.files.insert(dir.to_string(), IgnoreFile { size: local.size, modified: local.modified, matcher });
+        }
+    }
+
+    pub async fn refresh(&self, backend: &dyn StorageBackend, files: &[FileMetadata]) {
+        let mut stale = Vec::new();
+        let mut present = Vec::new();
+        if let Ok(rules) = self.rules.read() {
+            for file in files {
+                let Some(dir) = ignore_dir(&file.path) else { continue };
+                present.push(dir);
+                let cached = rules.files.get(dir).is_some_and(|f| f.size == file.size && f.modified == file.modified);
+                if !cached {
+                    stale.push(file);
+                }
+            }
+        }
+        if let Ok(mut rules) = self.rules.write() {
+            rules.files.retain(|dir, _| present.contains(&dir.as_str()));
+        }
+        for file in stale {
+            self.load(backend, file).await;
+        }
+    }
+
+    pub async fn reload(&self, backend: &dyn StorageBackend, path: &str) {
+        let Some(dir) = ignore_dir(path) else { return };
+        match backend.stat(path).await {
+            Ok(Some(local)) => self.load(backend, &local).await,
+            _ => {
+                if let Ok(mut rules) = self.rules.write() {
+                    rules.files.remove(dir);
+                }
+            }
+        }
+    }
+}
+
+pub struct Filtered {
+    inner: Arc<Box<dyn StorageBackend>>,
+    rules: Arc<IgnoreRules>,
+}
+
+impl Filtered {
+    pub fn new(inner: Arc<Box<dyn StorageBackend>>, rules: Arc<IgnoreRules>) -> Self {
+        Self { inner, rules }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for Filtered {
+    async fn list_files(&self) -> Result<Vec<FileMetadata>> {
+        let files = self.inner.list_files().await?;
+        self.rules.refresh(&**self.inner, &files).await;
+        Ok(self.rules.filter(files))
+    }
+
+    async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        self.inner.read_file(path).await
+    }
+
+    async fn write_file(&self, path: &str, content: &[u8]) -> Result<()> {
+        self.inner.write_file(path, content).await
+    }
+
+    async fn delete_file(&self, path: &str) -> Result<()> {
+        self.inner.delete_file(path).await
+    }
+
+    async fn read_range(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        self.inner.read_range(path, offset, len).await
+    }
+
+    async fn stat(&self, path: &str) -> Result<Option<FileMetadata>> {
+        self.inner.stat(path).await
+    }
+
+    async fn write_from(&self, path: &str, source: &Path) -> Result<()> {
+        self.inner.write_from(path, source).await
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+}
@@ -2,56 +2,86 @@ mod args;
 mod backend;
 mod backends;
 mod config;
+mod connection;
+mod crypto;
+mod filter;
+mod merge;
+mod mirror;
+mod pair;
+mod state;
+mod transfer;
 
 use args::{Args, Location};
 use backend::StorageBackend;
 use backends::folder::FolderBackend;
 use backends::ftp::FtpBackend;
-use backends::sftp::SftpBackend;
+use backends::s3::{S3Backend, S3Options};
+use backends::sftp::{SftpBackend, SshOptions};
+use backends::tar::TarBackend;
+use backends::webdav::{WebDavBackend, WebDavOptions};
 use backends::zip::ZipBackend;
 use clap::Parser;
-use common::{Message, calculate_hash};
+use common::{EncryptionInfo, FileMetadata, Message, Role, Subscription};
+use config::PairConfig;
 use futures_util::{SinkExt, StreamExt};
-use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
-use std::collections::{HashSet, HashMap};
-use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::collections::{HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
 use tokio::sync::mpsc;
-use tokio_tungstenite::connect_async;
 use tokio_tungstenite::tungstenite::Message as WsMessage;
-use dialoguer::{theme::ColorfulTheme, Input, Select};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, Password, Select};
 use anyhow::{Result, Context, anyhow};
+use pair::{LocalChange, Pair, Session};
+use crypto::Cipher;
 
 enum TransferState {
     Idle,
-    ExpectingBinary { path: String },
+    ExpectingChunk { session: usize, path: String, hash: String },
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
-    
-    let config_path = args.config.clone().unwrap_or_else(|| "logos_config.json".to_string());
-    let mut config = config::AppConfig::load(&config_path).await;
+fn passphrase(confirm: bool) -> Result<String> {
+    let theme = ColorfulTheme::default();
+    let mut prompt = Password::with_theme(&theme);
+    prompt.with_prompt("Storage Passphrase");
+    if confirm {
+        prompt.with_confirmation("Repeat Passphrase", "Passphrases do not match");
+    }
+    Ok(prompt.interact()?)
+}
 
-    let client_name = if let Some(name) = &config.client_name {
-        name.clone()
-    } else {
-        std::env::var("COMPUTERNAME")
-            .or_else(|_| std::env::var("HOSTNAME"))
-            .unwrap_or_else(|_| "Client".to_string())
-    };
+fn unlock_storage(config: &mut config::AppConfig, storage_id: &str, info: &EncryptionInfo, spare: &[String]) -> Result<Arc<Cipher>> {
+    let known = config.passphrases.get(storage_id).cloned();
+    for secret in known.iter().chain(spare) {
+        if let Ok(unlocked) = Cipher::unlock(secret, info) {
+            config.passphrases.insert(storage_id.to_string(), secret.clone());
+            return Ok(Arc::new(unlocked));
+        }
+    }
+    if known.is_some() {
+        eprintln!("[!] Saved passphrase for storage {} no longer matches", storage_id);
+    }
+    println!("[*] Passphrase required for storage {}", storage_id);
+    let secret = passphrase(false)?;
+    let unlocked = Cipher::unlock(&secret, info)?;
+    config.passphrases.insert(storage_id.to_string(), secret);
+    Ok(Arc::new(unlocked))
+}
 
-    let loc_input = if !args.locations.is_empty() {
-        args.locations.first().cloned()
-    } else {
-        config.location.clone()
-    };
+fn send_message(tx: &mpsc::UnboundedSender<WsMessage>, msg: &Message) -> Result<()> {
+    tx.send(WsMessage::Text(serde_json::to_string(msg)?)).map_err(|_| anyhow!("Channel closed"))
+}
+
+fn backoff_delay(attempt: u32) -> std::time::Duration {
+    let ceiling = (1000u64 << attempt.saturating_sub(1).min(6)).min(60_000);
+    let jittered = ceiling / 2 + rand::random::<u64>() % (ceiling / 2 + 1);
+    std::time::Duration::from_millis(jittered)
+}
 
-    let loc_raw = loc_input.ok_or_else(|| anyhow!("No location provided. Pass as arg or set in config."))?;
-    let loc_clean: String = loc_raw.trim().chars().filter(|c| !c.is_control()).collect();
+fn clean_location(raw: &str) -> String {
+    let loc_clean: String = raw.trim().chars().filter(|c| !c.is_control()).collect();
 
-    let loc_str = if (loc_clean.starts_with("ftp://") || loc_clean.starts_with("sftp://")) && loc_clean.contains('#') {
+    let is_ftp = ["ftp://", "ftpes://", "ftps://"].iter().any(|scheme| loc_clean.starts_with(scheme));
+    if is_ftp && loc_clean.contains('#') {
         if let Some(idx) = loc_clean.rfind('@') {
             let (creds, rest) = loc_clean.split_at(idx);
             if creds.contains('#') {
@@ -64,40 +94,40 @@ async fn main() -> Result<()> {
         }
     } else {
         loc_clean
-    };
+    }
+}
 
-    let backend: Arc<Box<dyn StorageBackend>> = if loc_str.starts_with("sftp://") || loc_str.starts_with("ssh://") {
+async fn open_backend(loc_str: &str, config: &config::AppConfig) -> Arc<Box<dyn StorageBackend>> {
+    if loc_str.starts_with("sftp://") || loc_str.starts_with("ssh://") {
          println!("[*] Initializing SFTP backend...");
-         match SftpBackend::new(&loc_str).await {
+         let options = SshOptions {
+             user: config.ssh_user.clone(),
+             password: config.ssh_password.clone(),
+             key_file: config.ssh_key.clone().map(PathBuf::from),
+             known_hosts: config.known_hosts.clone().map(PathBuf::from),
+         };
+         match SftpBackend::new(loc_str, options).await {
              Ok(sftp) => Arc::new(Box::new(sftp)),
              Err(e) => {
-                 eprintln!("[!] SFTP Error: {}", e);
+                 eprintln!("[!] SFTP Error: {:#}", e);
                  std::process::exit(1);
              }
          }
-    } else if loc_str.starts_with("ftp://") {
-        println!("[*] Initializing FTP backend...");
-        match FtpBackend::new(&loc_str) {
-            Ok(ftp) => Arc::new(Box::new(ftp)),
-            Err(e) => {
-                eprintln!("[!] FTP Error: {}", e);
-                std::process::exit(1);
-            }
-        }
     } else {
-        match Location::parse(&loc_str) {
+        match Location::parse(loc_str) {
             Ok(Location::Folder(path)) => Arc::new(Box::new(FolderBackend::new(path))),
             Ok(Location::Ftp(url)) => {
+                println!("[*] Initializing FTP backend...");
                 match FtpBackend::new(url.as_str()) {
                     Ok(ftp) => Arc::new(Box::new(ftp)),
                     Err(e) => {
-                        eprintln!("[!] FTP Init Error: {}", e);
+                        eprintln!("[!] FTP Error: {}", e);
                         std::process::exit(1);
                     },
                 }
             },
-            Ok(Location::Zip(path)) => {
-                match ZipBackend::new(path) {
+            Ok(Location::Zip { path, writable }) => {
+                match ZipBackend::new(path, writable) {
                     Ok(z) => Arc::new(Box::new(z)),
                     Err(e) => {
                          eprintln!("[!] ZIP Error: {}", e);
@@ -105,349 +135,489 @@ async fn main() -> Result<()> {
                     }
                 }
             },
+            Ok(Location::Tar(path)) => {
+                match TarBackend::new(path) {
+                    Ok(t) => Arc::new(Box::new(t)),
+                    Err(e) => {
+                         eprintln!("[!] TAR Error: {}", e);
+                         std::process::exit(1);
+                    }
+                }
+            },
+            Ok(Location::S3(url)) => {
+                println!("[*] Initializing S3 backend...");
+                let options = S3Options {
+                    endpoint: config.s3_endpoint.clone(),
+                    region: config.s3_region.clone(),
+                    access_key: config.s3_access_key.clone(),
+                    secret_key: config.s3_secret_key.clone(),
+                    path_style: config.s3_path_style,
+                };
+                match S3Backend::new(&url, options).await {
+                    Ok(s3) => Arc::new(Box::new(s3)),
+                    Err(e) => {
+                         eprintln!("[!] S3 Error: {:#}", e);
+                         std::process::exit(1);
+                    }
+                }
+            },
+            Ok(Location::WebDav(url)) => {
+                println!("[*] Initializing WebDAV backend...");
+                let options = WebDavOptions {
+                    user: config.webdav_user.clone(),
+                    password: config.webdav_password.clone(),
+                };
+                match WebDavBackend::new(&url, options).await {
+                    Ok(dav) => Arc::new(Box::new(dav)),
+                    Err(e) => {
+                         eprintln!("[!] WebDAV Error: {:#}", e);
+                         std::process::exit(1);
+                    }
+                }
+            },
             Err(e) => {
                 eprintln!("[!] Invalid location: {}", e);
                 std::process::exit(1);
             },
         }
-    };
-
-    println!("[+] Client started: {}", client_name);
-
-    let server_url = "ws://localhost:3000/ws/client";
-    let (ws_stream, _) = connect_async(server_url).await.context("Failed to connect to server")?;
-    let (mut ws_write, mut ws_read) = ws_stream.split();
-    let (tx, mut rx) = mpsc::unbounded_channel::<WsMessage>();
+    }
+}
 
-    let send_task = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            if ws_write.send(msg).await.is_err() { break; }
+fn join_next(tx: &mpsc::UnboundedSender<WsMessage>, pair: &Pair, client_name: &str, device_id: &str) -> Result<()> {
+    match &pair.storage_id {
+        Some(storage_id) => {
+            println!("[*] Auto-joining storage {} for {}", storage_id, pair.label);
+            send_message(tx, &Message::JoinStorage {
+                storage_id: storage_id.clone(),
+                client_name: client_name.to_string(),
+                device_id: Some(device_id.to_string()),
+                multiplex: true,
+                subscription: pair.subscription.clone(),
+            })
+        }
+        None => {
+            println!("[*] Select a storage for {}", pair.label);
+            send_message(tx, &Message::RequestStorageList)
         }
-    });
+    }
+}
 
-    let mut initial_files = Vec::new();
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    
+    let config_path = args.config.clone().unwrap_or_else(|| "logos_config.json".to_string());
+    let mut config = config::AppConfig::load(&config_path).await;
 
-    if let Some(target_id) = &config.storage_id {
-        println!("[*] Auto-joining storage: {}", target_id);
-        tx.send(WsMessage::Text(serde_json::to_string(&Message::JoinStorage { 
-            storage_id: target_id.clone(),
-            client_name: client_name.clone()
-        })?)).map_err(|_| anyhow!("Channel closed"))?;
+    let client_name = if let Some(name) = &config.client_name {
+        name.clone()
     } else {
-        tx.send(WsMessage::Text(serde_json::to_string(&Message::RequestStorageList)?)).map_err(|_| anyhow!("Channel closed"))?;
-    }
+        std::env::var("COMPUTERNAME")
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .unwrap_or_else(|_| "Client".to_string())
+    };
+    let device_id = config.device_id.get_or_insert_with(|| hex::encode(rand::random::<[u8; 16]>())).clone();
 
-    while let Some(Ok(msg)) = ws_read.next().await {
-        if let WsMessage::Text(text) = msg && let Ok(parsed) = serde_json::from_str::<Message>(&text) {
-            match parsed {
-                Message::StorageList { storages } => {
-                    println!("\nAvailable Storages:");
-                    let mut options: Vec<String> = storages.iter()
-                        .map(|s| format!("{} [{}]", s.name, s.id))
-                        .collect();
-                    options.push("Create New".to_string());
-                    options.push("Refresh".to_string());
-
-                    let selection = Select::with_theme(&ColorfulTheme::default())
-                        .with_prompt("Select Action")
-                        .default(0)
-                        .items(&options)
-                        .interact()?;
-
-                    if selection < storages.len() {
-                        let selected = &storages[selection];
-                        tx.send(WsMessage::Text(serde_json::to_string(&Message::JoinStorage { 
-                            storage_id: selected.id.clone(),
-                            client_name: client_name.clone()
-                        })?)).map_err(|_| anyhow!("Channel closed"))?;
-                    } else if selection == storages.len() {
-                        let name: String = Input::with_theme(&ColorfulTheme::default())
-                            .with_prompt("Storage Name")
-                            .interact_text()?;
-                        tx.send(WsMessage::Text(serde_json::to_string(&Message::CreateStorage { name: name.trim().to_string() })?)).map_err(|_| anyhow!("Channel closed"))?;
-                    } else {
-                        tx.send(WsMessage::Text(serde_json::to_string(&Message::RequestStorageList)?)).map_err(|_| anyhow!("Channel closed"))?;
-                    }
-                },
-                Message::Welcome { storage_id: sid, files } => {
-                    println!("[+] Joined storage successfully");
-                    initial_files = files;
-                    config.client_name = Some(client_name.clone());
-                    config.location = Some(loc_raw.clone());
-                    config.storage_id = Some(sid);
-                    config.save(&config_path).await;
-                    break;
-                },
-                Message::Error { message } => {
-                    eprintln!("[!] Server Error: {}", message);
-                    if config.storage_id.is_some() {
-                        config.storage_id = None;
-                    }
-                    tx.send(WsMessage::Text(serde_json::to_string(&Message::RequestStorageList)?)).map_err(|_| anyhow!("Channel closed"))?;
+    let configured = config.pairs.clone();
+    let mut specs: Vec<PairConfig> = if args.locations.is_empty() {
+        configured
+    } else {
+        args.locations.iter()
+            .map(|location| {
+                let known = configured.iter().find(|p| &p.location == location);
+                PairConfig {
+                    location: location.clone(),
+                    storage_id: known.and_then(|p| p.storage_id.clone()),
+                    subscription: known.map(|p| p.subscription.clone()).unwrap_or_default(),
                 }
-                _ => {}
-            }
+            })
+            .collect()
+    };
+    if !args.include.is_empty() || !args.exclude.is_empty() {
+        let subscription = Subscription { include: args.include.clone(), exclude: args.exclude.clone() };
+        for spec in &mut specs {
+            spec.subscription = subscription.clone();
+        }
+    }
+    if let Some(legacy) = config.storage_id.clone()
+        && let Some(first) = specs.first_mut()
+        && first.storage_id.is_none() {
+            first.storage_id = Some(legacy);
+        }
+    let mut spare_passphrases: Vec<String> = config.passphrase.clone().into_iter().collect();
+    if specs.is_empty() {
+        return Err(anyhow!("No location provided. Pass as arg or set in config."));
+    }
+    let mut mapped = HashSet::new();
+    for spec in &specs {
+        if let Some(storage_id) = &spec.storage_id && !mapped.insert(storage_id.clone()) {
+            return Err(anyhow!("Storage {} is mapped to more than one location", storage_id));
         }
     }
 
-    println!("[*] Starting synchronization...");
-    let synced_hashes = Arc::new(Mutex::new(HashMap::<String, String>::new()));
-    let pending_deletes = Arc::new(Mutex::new(HashSet::<String>::new()));
-    let mut transfer_state = TransferState::Idle;
+    let mut pairs = Vec::new();
+    for spec in specs {
+        let source = clean_location(&spec.location);
+        let backend = open_backend(&source, &config).await;
+        pairs.push(Pair::new(spec, &source, backend));
+    }
 
-    if let Ok(local_files) = backend.list_files().await {
-        println!("[*] Found {} local files", local_files.len());
+    if args.mirror {
+        let [first, second] = pairs.as_slice() else {
+            return Err(anyhow!("Mirror mode needs exactly two locations"));
+        };
+        return mirror::run(first, second, &config_path).await;
+    }
 
-        for local in &local_files {
-            let remote = initial_files.iter().find(|f| f.path == local.path);
-            let needs_upload = match remote {
-                None => true, 
-                Some(r) => local.modified > r.modified
+    let grant = match &args.grant {
+        Some(spec) => {
+            let (user, role) = spec.split_once(':').ok_or_else(|| anyhow!("Expected USER:ROLE, got {}", spec))?;
+            let role = match role {
+                "none" => None,
+                other => Some(Role::parse(other).ok_or_else(|| anyhow!("Unknown role: {}", other))?),
             };
+            Some((user.to_string(), role))
+        }
+        None => None,
+    };
 
-            if needs_upload {
-                if let Ok(content) = backend.read_file(&local.path).await {
-                     let hash = calculate_hash(&content);
-                     if let Ok(mut guard) = synced_hashes.lock() {
-                        guard.insert(local.path.clone(), hash);
-                     }
-
-                     let header = Message::StartTransfer { 
-                        path: local.path.clone(), 
-                        size: content.len() as u64, 
-                        target_version: 0 
-                    };
-                    tx.send(WsMessage::Text(serde_json::to_string(&header)?)).map_err(|_| anyhow!("Channel closed"))?;
-                    tx.send(WsMessage::Binary(content)).map_err(|_| anyhow!("Channel closed"))?;
-                    println!("[^] Uploading: {}", local.path);
-                }
-            } else if let Some(r) = remote {
-                let will_download = !r.is_deleted && r.modified > local.modified;
-                if !will_download && !r.is_deleted
-                    && let Ok(content) = backend.read_file(&local.path).await {
-                        let hash = calculate_hash(&content);
-                        if let Ok(mut guard) = synced_hashes.lock() {
-                             guard.insert(local.path.clone(), hash);
-                        }
-                    }
-            }
+    let token = match args.secret.clone().or_else(|| config.token.clone()) {
+        Some(token) => token,
+        None => Password::with_theme(&ColorfulTheme::default())
+            .with_prompt("Access Token")
+            .interact()?,
+    };
+
+    println!("[+] Client started: {}", client_name);
+
+    let server_url = args.server.clone().or_else(|| config.server_url.clone())
+        .unwrap_or_else(|| connection::DEFAULT_SERVER_URL.to_string());
+    let ca_cert = args.ca_cert.clone().or_else(|| config.ca_cert.clone());
+    let pinned_cert = args.pin_cert.clone().or_else(|| config.pinned_cert.clone());
+    let endpoint = connection::Endpoint::new(&server_url, ca_cert.as_deref(), pinned_cert.as_deref())?;
+    println!("[*] Server: {}", endpoint.url());
+
+    let one_shot = args.create_user.is_some() || grant.is_some() || args.history.is_some() || args.set_ignore.is_some();
+    let scoped_command = grant.is_some() || args.history.is_some() || args.set_ignore.is_some() || args.restore.is_some();
+    let target = match &args.storage {
+        Some(selector) => pairs.iter()
+            .position(|p| p.storage_id.as_deref() == Some(selector.as_str()) || &p.location == selector || &p.label == selector)
+            .with_context(|| format!("No configured location or storage matches {}", selector))?,
+        None if scoped_command && pairs.len() > 1 => {
+            return Err(anyhow!("{} locations are configured; pass --storage <ID|LOCATION> to choose one for --history, --restore, --grant or --set-ignore", pairs.len()));
         }
+        None => 0,
+    };
+    let (changes_tx, mut changes_rx) = mpsc::unbounded_channel::<(usize, LocalChange)>();
+    let mut restore = args.restore.clone().zip(args.to_version);
+    let mut attempt = 0u32;
 
-        if !backend.is_read_only() {
-            for remote in &initial_files {
-                if remote.is_deleted { continue; }
-                let local = local_files.iter().find(|f| f.path == remote.path);
-                if local.is_none() || remote.modified > local.unwrap().modified {
-                    println!("[v] Requesting download: {}", remote.path);
-                    let msg = Message::RequestFile { path: remote.path.clone() };
-                    tx.send(WsMessage::Text(serde_json::to_string(&msg)?)).map_err(|_| anyhow!("Channel closed"))?;
-                }
-            }
+    loop {
+        if attempt > 0 {
+            let delay = backoff_delay(attempt);
+            println!("[*] Reconnecting in {:.1}s...", delay.as_secs_f32());
+            tokio::time::sleep(delay).await;
         }
-    }
+        attempt += 1;
 
-    let mut _watcher: Option<RecommendedWatcher> = None;
-
-    if !backend.is_read_only() {
-        if let Ok(Location::Folder(raw_path)) = Location::parse(&loc_str) {
-            let tx_w = tx.clone();
-            let backend_w = backend.clone();
-            let hashes_w = synced_hashes.clone();
-            let deletes_w = pending_deletes.clone();
-            
-            let abs_root = std::fs::canonicalize(&raw_path).unwrap_or(raw_path);
-            let (notify_tx, mut notify_rx) = mpsc::unbounded_channel();
-            
-            let mut watcher = RecommendedWatcher::new(move |res: Result<Event, notify::Error>| {
-                if let Ok(event) = res { notify_tx.send(event).ok(); }
-            }, notify::Config::default()).context("Failed to create watcher")?;
-            
-            if let Err(e) = watcher.watch(&abs_root, RecursiveMode::Recursive) {
-                eprintln!("[!] Watcher error: {}", e);
+        let ws_stream = match endpoint.connect().await {
+            Ok(stream) => stream,
+            Err(e) if one_shot => return Err(e).context("Failed to connect to server"),
+            Err(e) => {
+                eprintln!("[!] Failed to connect to server: {}", e);
+                continue;
             }
-            _watcher = Some(watcher);
-            
-            tokio::spawn(async move {
-                let to_relative = |sys_path: &Path| -> Option<String> {
-                     sys_path.strip_prefix(&abs_root).ok()
-                        .map(|p| p.to_string_lossy().replace("\\", "/"))
-                        .filter(|s| !s.is_empty())
-                };
+        };
+        let (mut ws_write, mut ws_read) = ws_stream.split();
+        let (tx, mut rx) = mpsc::unbounded_channel::<WsMessage>();
 
-                while let Some(event) = notify_rx.recv().await {
-                    match event.kind {
-                        EventKind::Create(_) | EventKind::Modify(_) => {
-                            for path in event.paths {
-                                if let Some(rel) = to_relative(&path) {
-                                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
-                                    
-                                    if let Ok(content) = backend_w.read_file(&rel).await {
-                                        let hash = calculate_hash(&content);
-                                        
-                                        let should_upload = if let Ok(mut guard) = hashes_w.lock() {
-                                            if let Some(h) = guard.get(&rel) {
-                                                if h == &hash { 
-                                                    false 
-                                                } else {
-                                                    guard.insert(rel.clone(), hash);
-                                                    true
-                                                }
-                                            } else {
-                                                guard.insert(rel.clone(), hash);
-                                                true
-                                            }
-                                        } else {
-                                            eprintln!("[!] Mutex poisoned");
-                                            false
-                                        };
-
-                                        if !should_upload { continue; }
-
-                                        let header = Message::StartTransfer { 
-                                            path: rel.clone(), 
-                                            size: content.len() as u64, 
-                                            target_version: 0 
-                                        };
-                                        if let Ok(json) = serde_json::to_string(&header) {
-                                            let _ = tx_w.send(WsMessage::Text(json));
-                                            let _ = tx_w.send(WsMessage::Binary(content));
-                                            println!("[^] Uploading: {}", rel);
-                                        }
-                                    }
-                                }
+        let send_task = tokio::spawn(async move {
+            let mut open = true;
+            while let Some(msg) = rx.recv().await {
+                if !open { continue; }
+                open = ws_write.send(msg).await.is_ok();
+            }
+        });
+
+        let mut welcomes: Vec<(Vec<FileMetadata>, Option<Arc<Cipher>>)> = Vec::new();
+        let mut backlog = VecDeque::new();
+        let mut authenticated = false;
+
+        send_message(&tx, &Message::Authenticate { token: token.clone() })?;
+
+        while let Some(Ok(msg)) = ws_read.next().await {
+            if let WsMessage::Text(text) = msg && let Ok(parsed) = serde_json::from_str::<Message>(&text) {
+                let joining = welcomes.len();
+                match parsed {
+                    Message::Authenticated { user } => {
+                        println!("[+] Authenticated as {}", user);
+                        authenticated = true;
+                        if config.token.as_deref() != Some(token.as_str()) {
+                            config.token = Some(token.clone());
+                            config.save(&config_path).await;
+                        }
+
+                        if let Some(name) = &args.create_user {
+                            send_message(&tx, &Message::CreateUser { name: name.clone(), admin: args.admin })?;
+                        } else {
+                            join_next(&tx, &pairs[joining], &client_name, &device_id)?;
+                        }
+                    },
+                    Message::UserCreated { name, token } => {
+                        println!("[+] Created user {} with token: {}", name, token);
+                        send_task.abort();
+                        return Ok(());
+                    },
+                    Message::RoleUpdated { user, role, .. } => {
+                        println!("[+] Role of {} set to {}", user, role.map(|r| r.as_str()).unwrap_or("none"));
+                        send_task.abort();
+                        return Ok(());
+                    },
+                    Message::IgnorePatternsUpdated { patterns, .. } if one_shot => {
+                        if patterns.is_empty() {
+                            println!("[+] Cleared ignore patterns");
+                        } else {
+                            println!("[+] Ignore patterns set to: {}", patterns.join(" "));
+                        }
+                        send_task.abort();
+                        return Ok(());
+                    },
+                    Message::StorageList { storages } => {
+                        println!("\nAvailable Storages:");
+                        let mut options: Vec<String> = storages.iter()
+                            .map(|s| {
+                                let lock = if s.encryption.is_some() { " (encrypted)" } else { "" };
+                                format!("{} [{}]{}", s.name, s.id, lock)
+                            })
+                            .collect();
+                        options.push("Create New".to_string());
+                        options.push("Refresh".to_string());
+
+                        let selection = Select::with_theme(&ColorfulTheme::default())
+                            .with_prompt(format!("Select Action for {}", pairs[joining].label))
+                            .default(0)
+                            .items(&options)
+                            .interact()?;
+
+                        if selection < storages.len() {
+                            let selected = &storages[selection];
+                            if let Some(other) = pairs.iter().find(|p| p.storage_id.as_deref() == Some(selected.id.as_str())) {
+                                eprintln!("[!] Storage {} is already synced with {}", selected.name, other.label);
+                                send_message(&tx, &Message::RequestStorageList)?;
+                                continue;
                             }
+                            send_message(&tx, &Message::JoinStorage {
+                                storage_id: selected.id.clone(),
+                                client_name: client_name.clone(),
+                                device_id: Some(device_id.clone()),
+                                multiplex: true,
+                                subscription: pairs[joining].subscription.clone(),
+                            })?;
+                        } else if selection == storages.len() {
+                            let name: String = Input::with_theme(&ColorfulTheme::default())
+                                .with_prompt("Storage Name")
+                                .interact_text()?;
+                            let encrypted = Confirm::with_theme(&ColorfulTheme::default())
+                                .with_prompt("Encrypt file contents end-to-end?")
+                                .default(false)
+                                .interact()?;
+                            let encryption = if encrypted {
+                                let encrypt_paths = Confirm::with_theme(&ColorfulTheme::default())
+                                    .with_prompt("Also encrypt file paths?")
+                                    .default(false)
+                                    .interact()?;
+                                let secret = passphrase(true)?;
+                                let info = crypto::new_storage_encryption(&secret, encrypt_paths)?;
+                                spare_passphrases.push(secret);
+                                Some(info)
+                            } else {
+                                None
+                            };
+                            send_message(&tx, &Message::CreateStorage { name: name.trim().to_string(), encryption })?;
+                        } else {
+                            send_message(&tx, &Message::RequestStorageList)?;
                         }
-                        EventKind::Remove(_) => {
-                             for path in event.paths {
-                                if let Some(rel) = to_relative(&path) {
-                                    if let Ok(mut d_guard) = deletes_w.lock() && d_guard.contains(&rel) { 
-                                        d_guard.remove(&rel); 
-                                        continue; 
-                                    }
-                                    if let Ok(mut guard) = hashes_w.lock() {
-                                        guard.remove(&rel);
-                                    }
-                                    let msg = Message::DeleteFile { path: rel.clone() };
-                                    if let Ok(json) = serde_json::to_string(&msg) {
-                                        let _ = tx_w.send(WsMessage::Text(json));
-                                        println!("[x] Deleting: {}", rel);
-                                    }
-                                }
+                    },
+                    Message::Welcome { storage_id: sid, files, encryption, ignore } => {
+                        let pair = &mut pairs[joining];
+                        println!("[+] [{}] Joined storage {}", pair.label, sid);
+                        pair.ignore.set_patterns(&ignore);
+                        let mut files = files;
+                        let mut cipher = None;
+                        if let Some(info) = encryption {
+                            let unlocked = unlock_storage(&mut config, &sid, &info, &spare_passphrases)?;
+                            println!("[+] [{}] End-to-end encryption enabled{}", pair.label, if unlocked.encrypts_paths() { " (paths encrypted)" } else { "" });
+                            if unlocked.encrypts_paths() {
+                                files = files.into_iter()
+                                    .map(|meta| unlocked.open_meta(meta))
+                                    .collect::<Result<_>>()
+                                    .with_context(|| format!("Failed to decrypt file list of storage {}, refusing to sync", sid))?;
                             }
+                            cipher = Some(unlocked);
                         }
-                        _ => {}
+                        pair.storage_id = Some(sid.clone());
+                        welcomes.push((files, cipher.clone()));
+                        attempt = 1;
+                        config.client_name = Some(client_name.clone());
+                        config.pairs = pairs.iter().map(Pair::config).collect();
+                        config.storage_id = None;
+                        if welcomes.len() == pairs.len() {
+                            config.passphrase = None;
+                        }
+                        config.server_url = Some(server_url.clone());
+                        config.ca_cert = ca_cert.clone();
+                        config.pinned_cert = pinned_cert.clone();
+                        config.save(&config_path).await;
+                        if joining == target && let Some((user, role)) = &grant {
+                            send_message(&tx, &Message::SetRole { storage_id: sid, user: user.clone(), role: *role })?;
+                            continue;
+                        }
+                        if joining == target && let Some(patterns) = &args.set_ignore {
+                            send_message(&tx, &Message::SetIgnorePatterns { storage_id: sid, patterns: patterns.clone() })?;
+                            continue;
+                        }
+                        if joining == target && let Some(path) = &args.history {
+                            send_message(&tx, &pair::scoped(&sid, cipher.as_deref(), Message::ListVersions { path: path.clone() })?)?;
+                            continue;
+                        }
+                        if welcomes.len() < pairs.len() {
+                            join_next(&tx, &pairs[welcomes.len()], &client_name, &device_id)?;
+                            continue;
+                        }
+                        break;
+                    },
+                    Message::Scoped { storage_id, message } if one_shot => {
+                        let cipher = pairs.iter().position(|p| p.storage_id.as_deref() == Some(storage_id.as_str()))
+                            .and_then(|i| welcomes.get(i))
+                            .and_then(|(_, cipher)| cipher.clone());
+                        let message = match &cipher {
+                            Some(cipher) => cipher.open_paths(*message)?,
+                            None => *message,
+                        };
+                        match message {
+                            Message::VersionList { path, versions } => {
+                                pair::print_history(&path, &versions);
+                                send_task.abort();
+                                return Ok(());
+                            }
+                            Message::Error { message } => return Err(anyhow!("Server Error: {}", message)),
+                            _ => {}
+                        }
+                    }
+                    Message::Scoped { .. } => backlog.push_back(WsMessage::Text(text)),
+                    Message::Error { message } => {
+                        eprintln!("[!] Server Error: {}", message);
+                        if !authenticated || (one_shot && !welcomes.is_empty()) || args.create_user.is_some() {
+                            return Err(anyhow!("Server Error: {}", message));
+                        }
+                        pairs[joining].storage_id = None;
+                        send_message(&tx, &Message::RequestStorageList)?;
                     }
+                    _ => {}
                 }
-            });
-        } else {
-             println!("[*] Starting remote polling (10s interval)");
-             let tx_poll = tx.clone();
-             let backend_poll = backend.clone();
-             let hashes_poll = synced_hashes.clone();
-             
-             tokio::spawn(async move {
-                 loop {
-                     tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-                     if let Ok(files) = backend_poll.list_files().await {
-                         for file in files {
-                             let should_upload = {
-                                 if let Ok(guard) = hashes_poll.lock() {
-                                     !guard.contains_key(&file.path)
-                                 } else {
-                                     false
-                                 }
-                             };
-
-                             if should_upload && let Ok(content) = backend_poll.read_file(&file.path).await {
-                                 let hash = calculate_hash(&content);
-                                 if let Ok(mut guard) = hashes_poll.lock() {
-                                     if let Some(h) = guard.get(&file.path) && h == &hash { continue; }
-                                     guard.insert(file.path.clone(), hash);
-                                 }
-                                 
-                                 let header = Message::StartTransfer { 
-                                    path: file.path.clone(), 
-                                    size: content.len() as u64, 
-                                    target_version: 0 
-                                 };
-                                 if let Ok(json) = serde_json::to_string(&header) && tx_poll.send(WsMessage::Text(json)).is_ok() {
-                                     let _ = tx_poll.send(WsMessage::Binary(content));
-                                     println!("[^] Uploading (Poll): {}", file.path);
-                                 }
-                             }
-                         }
-                     }
-                 }
-             });
+            }
         }
-    }
 
-    while let Some(Ok(msg)) = ws_read.next().await {
-        match msg {
-            WsMessage::Text(text) => {
-                if let Ok(parsed) = serde_json::from_str::<Message>(&text) {
-                    match parsed {
-                        Message::StartTransfer { path, .. } => {
-                            if backend.is_read_only() {
-                                println!("[!] Skipped update for read-only backend: {}", path);
-                            } else {
-                                transfer_state = TransferState::ExpectingBinary { path };
-                            }
+        if one_shot {
+            send_task.abort();
+            return Err(anyhow!("Disconnected from server"));
+        }
+        if welcomes.len() < pairs.len() {
+            send_task.abort();
+            println!("[!] Disconnected from server.");
+            continue;
+        }
+
+        println!("[*] Starting synchronization...");
+        let mut sessions = Vec::new();
+        for (pair, (files, cipher)) in pairs.iter_mut().zip(welcomes) {
+            let storage_id = pair.storage_id.clone().context("Pair was not joined")?;
+            let sync_state = pair.sync_state(&config_path, &storage_id)?;
+            let mut session = Session::new(pair, storage_id, sync_state, tx.clone(), cipher, &files);
+            if let Err(e) = session.reconcile(files).await {
+                eprintln!("[!] [{}] Initial sync failed: {}", session.label, e);
+            }
+            sessions.push(session);
+        }
+
+        if let Some((path, version)) = restore.take() && let Some(session) = sessions.get(target) {
+            println!("[*] Restoring {} to version {}", path, version);
+            session.send(Message::RestoreVersion { path, version })?;
+        }
+
+        let queued = changes_rx.len();
+        if queued > 0 {
+            println!("[*] Replaying {} local changes made while disconnected", queued);
+        }
+
+        for (index, pair) in pairs.iter_mut().enumerate() {
+            pair.watch(index, &changes_tx)?;
+        }
+
+        let mut transfer_state = TransferState::Idle;
+        loop {
+            let msg = match backlog.pop_front() {
+                Some(msg) => msg,
+                None => tokio::select! {
+                    msg = ws_read.next() => match msg {
+                        Some(Ok(msg)) => msg,
+                        _ => break,
+                    },
+                    Some(change) = changes_rx.recv() => {
+                        let mut batch = vec![change];
+                        while let Ok(more) = changes_rx.try_recv() {
+                            batch.push(more);
                         }
-                        Message::DeleteFile { path } => {
-                            if !backend.is_read_only() {
-                                println!("[x] Remote delete: {}", path);
-                                if let Ok(mut guard) = pending_deletes.lock() {
-                                    guard.insert(path.clone());
-                                }
-                                let _ = backend.delete_file(&path).await;
-                                if let Ok(mut guard) = synced_hashes.lock() {
-                                    guard.remove(&path);
-                                }
+                        let mut seen = HashSet::new();
+                        let mut latest: Vec<_> = batch.into_iter().rev().filter(|(index, c)| seen.insert((*index, c.path().to_string()))).collect();
+                        latest.reverse();
+                        for (index, change) in latest {
+                            let Some(session) = sessions.get(index) else { continue };
+                            if let Err(e) = session.handle_local_change(change).await {
+                                eprintln!("[!] Failed to queue local change: {}", e);
                             }
                         }
-                        Message::ConflictDetected { path, server_version } => {
-                            println!("[!] Conflict detected: {} (v{}). Saving local copy.", path, server_version);
-                            let p_obj = PathBuf::from(&path);
-                            let stem = p_obj.file_stem().unwrap_or_default().to_string_lossy();
-                            let ext = p_obj.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
-                            let ts = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
-                            let conflict_path = format!("{}_conflict_{}{}", stem, ts, ext);
-                            
-                            if let Ok(mut guard) = pending_deletes.lock() {
-                                guard.insert(path.clone());
+                        continue;
+                    }
+                },
+            };
+            match msg {
+                WsMessage::Text(text) => {
+                    match serde_json::from_str::<Message>(&text) {
+                        Ok(Message::Scoped { storage_id, message }) => {
+                            let Some(index) = sessions.iter().position(|s| s.storage_id == storage_id) else { continue };
+                            let session = &mut sessions[index];
+                            let message = match session.open(*message) {
+                                Ok(opened) => opened,
+                                Err(e) => {
+                                    eprintln!("[!] Failed to decrypt message: {}", e);
+                                    continue;
+                                }
+                            };
+                            if let Message::TransferChunk { path, hash, .. } = message {
+                                transfer_state = TransferState::ExpectingChunk { session: index, path, hash };
+                                continue;
                             }
-                            if let Ok(content) = backend.read_file(&path).await 
-                                && backend.write_file(&conflict_path, &content).await.is_ok() {
-                                    println!("[*] Saved conflict to {}", conflict_path);
-                                    if backend.delete_file(&path).await.is_ok() {
-                                        let _ = tx.send(WsMessage::Text(serde_json::to_string(&Message::RequestFile { path: path.clone() })?));
-                                    }
+                            if let Err(e) = session.handle_message(message).await {
+                                eprintln!("[!] [{}] Failed to handle server message: {}", session.label, e);
                             }
                         }
+                        Ok(Message::Error { message }) => eprintln!("[!] Server Error: {}", message),
                         _ => {}
                     }
                 }
-            }
-            WsMessage::Binary(data) => {
-                if let TransferState::ExpectingBinary { path } = transfer_state {
-                    println!("[v] Downloading: {}", path);
-                    let hash = calculate_hash(&data);
-                    if let Ok(mut guard) = synced_hashes.lock() {
-                        guard.insert(path.clone(), hash);
-                    }
-                    
-                    if let Err(e) = backend.write_file(&path, &data).await {
-                        eprintln!("[!] Write error for {}: {}", path, e);
-                    }
-                    transfer_state = TransferState::Idle;
+                WsMessage::Binary(data) => {
+                    if let TransferState::ExpectingChunk { session, path, hash } = std::mem::replace(&mut transfer_state, TransferState::Idle)
+                        && let Some(session) = sessions.get_mut(session)
+                        && let Err(e) = session.on_chunk(&path, &hash, &data).await {
+                            eprintln!("[!] [{}] Failed to store chunk of {}: {}", session.label, path, e);
+                        }
                 }
+                _ => {}
             }
-            _ => {}
         }
+
+        send_task.abort();
+        println!("[!] Disconnected from server.");
     }
-    
-    send_task.abort();
-    println!("[!] Disconnected from server.");
-    Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,73 @@
+use common::FileMetadata;
+use diffy::{ConflictStyle, MergeOptions};
+
+pub const MAX_MERGE_SIZE: u64 = 16 * 1024 * 1024;
+
+pub enum MergeResult {
+    Clean(String),
+    Conflicted(String),
+}
+
+pub struct PendingMerge {
+    pub current: FileMetadata,
+    base_version: u64,
+    local: String,
+    base: Option<String>,
+    theirs: Option<String>,
+}
+
+pub fn merge_text(base: &str, ours: &str, theirs: &str) -> MergeResult {
+    match MergeOptions::new()
+        .set_conflict_style(ConflictStyle::Merge)
+        .merge(base, ours, theirs)
+    {
+        Ok(merged) => MergeResult::Clean(merged),
+        Err(marked) => MergeResult::Conflicted(marked),
+    }
+}
+
+pub fn as_text(data: Vec<u8>) -> Option<String> {
+    if data.len() as u64 > MAX_MERGE_SIZE || data.contains(&0) {
+        return None;
+    }
+    String::from_utf8(data).ok()
+}
+
+impl PendingMerge {
+    pub fn new(current: FileMetadata, base: &FileMetadata, local: Vec<u8>) -> Option<Self> {
+        if current.size > MAX_MERGE_SIZE || base.size > MAX_MERGE_SIZE || base.is_deleted {
+            return None;
+        }
+        Some(Self {
+            current,
+            base_version: base.version,
+            local: as_text(local)?,
+            base: None,
+            theirs: None,
+        })
+    }
+
+    pub fn accept(&mut self, version: u64, data: Vec<u8>) -> Result<(), ()> {
+        let text = as_text(data).ok_or(())?;
+        if version == self.base_version {
+            self.base = Some(text);
+        } else if version == self.current.version {
+            self.theirs = Some(text);
+        }
+        Ok(())
+    }
+
+    pub fn local(&self) -> &str {
+        &self.local
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.base.is_some() && self.theirs.is_some()
+    }
+
+    pub fn merge(&self) -> MergeResult {
+        let base = self.base.as_deref().unwrap_or_default();
+        let theirs = self.theirs.as_deref().unwrap_or_default();
+        merge_text(base, &self.local, theirs)
+    }
+}
@@ -0,0 +1,252 @@
+use crate::backend::StorageBackend;
+use crate::filter::IgnoreRules;
+use crate::merge::{self, MAX_MERGE_SIZE, MergeResult};
+use crate::pair::{self, Pair};
+use crate::state::SyncState;
+use crate::transfer;
+use anyhow::{Result, anyhow};
+use common::{CHUNK_SIZE, ContentHasher, FileMetadata, calculate_hash};
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+const MIRROR_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Side<'a> {
+    label: &'a str,
+    location: &'a str,
+    backend: &'a dyn StorageBackend,
+    ignore: &'a IgnoreRules,
+    state: SyncState,
+    files: HashMap<String, FileMetadata>,
+}
+
+impl<'a> Side<'a> {
+    fn open(pair: &'a Pair, config_path: &str, key: &str) -> Result<Self> {
+        Ok(Self {
+            label: &pair.label,
+            location: &pair.location,
+            backend: &**pair.backend,
+            ignore: &pair.ignore,
+            state: SyncState::open(config_path, &format!("{}:{}", key, pair.location))?,
+            files: HashMap::new(),
+        })
+    }
+
+    async fn refresh(&mut self) -> Result<()> {
+        self.files = self.backend.list_files().await?.into_iter().map(|f| (f.path.clone(), f)).collect();
+        Ok(())
+    }
+
+    fn is_fresh(&self, path: &str) -> bool {
+        let local = self.files.get(path);
+        self.state.get(path).is_some_and(|e| local.is_some_and(|l| e.matches(l)))
+    }
+
+    async fn hash(&self, path: &str) -> Result<Option<String>> {
+        let Some(local) = self.files.get(path) else { return Ok(None) };
+        if let Some(entry) = self.state.get(path) && entry.matches(local) {
+            return Ok(Some(entry.hash));
+        }
+        Ok(Some(transfer::scan_file(self.backend, path, None).await?.hash))
+    }
+
+    async fn record(&mut self, path: &str, hash: &str) {
+        let local = self.backend.stat(path).await.ok().flatten();
+        self.state.record(path, 0, hash, local.as_ref());
+    }
+
+    async fn text(&self, path: &str, hash: &str) -> Result<Option<String>> {
+        let Some(local) = self.backend.stat(path).await? else { return Ok(None) };
+        if local.size > MAX_MERGE_SIZE {
+            return Ok(None);
+        }
+        let data = self.backend.read_file(path).await?;
+        if calculate_hash(&data) != hash {
+            return Ok(None);
+        }
+        Ok(merge::as_text(data))
+    }
+
+    async fn keep_base(&mut self, path: &str, hash: &str) {
+        match self.text(path, hash).await {
+            Ok(Some(text)) => self.state.save_base(path, hash, &text),
+            _ => self.state.forget_base(path),
+        }
+    }
+}
+
+async fn copy(from: &Side<'_>, to: &Side<'_>, path: &str, hash: &str) -> Result<()> {
+    let dir = std::env::temp_dir().join("logos").join("mirror");
+    fs::create_dir_all(&dir).await?;
+    let part = dir.join(format!("{}.part", calculate_hash(format!("{}:{}", path, hash).as_bytes())));
+
+    let mut file = fs::File::create(&part).await?;
+    let mut hasher = ContentHasher::new();
+    let mut offset = 0u64;
+    loop {
+        let data = from.backend.read_range(path, offset, CHUNK_SIZE).await?;
+        hasher.update(&data);
+        file.write_all(&data).await?;
+        offset += data.len() as u64;
+        if data.len() < CHUNK_SIZE { break; }
+    }
+    file.flush().await?;
+    drop(file);
+
+    let result = if hasher.finish() != hash {
+        Err(anyhow!("{} changed on {} while copying", path, from.label))
+    } else {
+        to.backend.write_from(path, &part).await
+    };
+    let _ = fs::remove_file(&part).await;
+    result
+}
+
+async fn propagate(from: &mut Side<'_>, to: &mut Side<'_>, path: &str, hash: Option<&str>) -> Result<()> {
+    if to.backend.is_read_only() {
+        return Ok(());
+    }
+    match hash {
+        Some(hash) => {
+            copy(from, to, path, hash).await?;
+            println!("[+] Mirrored {}: {} -> {}", path, from.label, to.label);
+            from.record(path, hash).await;
+            to.record(path, hash).await;
+        }
+        None => {
+            to.backend.delete_file(path).await?;
+            println!("[x] Deleted {} from {}", path, to.label);
+            from.state.forget(path);
+            to.state.forget(path);
+        }
+    }
+    Ok(())
+}
+
+async fn merge<'a>(a: &mut Side<'a>, b: &mut Side<'a>, path: &str, base: &str, ha: &str, hb: &str) -> Result<Option<MergeResult>> {
+    if a.backend.is_read_only() || b.backend.is_read_only() {
+        return Ok(None);
+    }
+    let Some(base) = a.state.base(path, base) else { return Ok(None) };
+    let (Some(ours), Some(theirs)) = (a.text(path, ha).await?, b.text(path, hb).await?) else { return Ok(None) };
+    let merged = match merge::merge_text(&base, &ours, &theirs) {
+        MergeResult::Clean(merged) => merged,
+        conflicted => return Ok(Some(conflicted)),
+    };
+
+    let hash = calculate_hash(merged.as_bytes());
+    a.backend.write_file(path, merged.as_bytes()).await?;
+    b.backend.write_file(path, merged.as_bytes()).await?;
+    a.record(path, &hash).await;
+    b.record(path, &hash).await;
+    a.state.save_base(path, &hash, &merged);
+    println!("[*] Merged changes to {} from {} and {}", path, a.label, b.label);
+    Ok(Some(MergeResult::Clean(merged)))
+}
+
+async fn resolve_conflict<'a>(a: &mut Side<'a>, b: &mut Side<'a>, path: &str, base: Option<&str>, ha: &str, hb: &str) -> Result<()> {
+    let marked = match base {
+        Some(base) => match merge(a, b, path, base, ha, hb).await? {
+            Some(MergeResult::Clean(_)) => return Ok(()),
+            Some(MergeResult::Conflicted(marked)) => Some(marked),
+            None => None,
+        },
+        None => None,
+    };
+
+    let a_wins = if a.backend.is_read_only() || b.backend.is_read_only() {
+        a.backend.is_read_only()
+    } else {
+        a.location <= b.location
+    };
+    let (winner, loser, hash) = if a_wins { (a, b, ha) } else { (b, a, hb) };
+
+    let conflict_path = pair::conflict_path(path);
+    let content = match marked {
+        Some(marked) => marked.into_bytes(),
+        None => loser.backend.read_file(path).await?,
+    };
+    loser.backend.write_file(&conflict_path, &content).await?;
+    println!("[!] Conflict on {}: kept the copy from {}, saved {} as {}", path, winner.label, loser.label, conflict_path);
+    propagate(winner, loser, path, Some(hash)).await
+}
+
+async fn reconcile<'a>(a: &mut Side<'a>, b: &mut Side<'a>, path: &str) -> Result<()> {
+    let base = a.state.get(path).map(|e| e.hash);
+    let ha = a.hash(path).await?;
+    let hb = b.hash(path).await?;
+
+    if ha == hb {
+        match &ha {
+            Some(hash) if base.as_ref() != Some(hash) || !a.is_fresh(path) || !b.is_fresh(path) => {
+                a.record(path, hash).await;
+                b.record(path, hash).await;
+            }
+            Some(_) => {}
+            None => {
+                a.state.forget(path);
+                b.state.forget(path);
+            }
+        }
+    } else {
+        match (ha != base, hb != base) {
+            (true, false) => propagate(a, b, path, ha.as_deref()).await?,
+            (false, true) => propagate(b, a, path, hb.as_deref()).await?,
+            _ => match (ha, hb) {
+                (Some(ha), Some(hb)) => resolve_conflict(a, b, path, base.as_deref(), &ha, &hb).await?,
+                (Some(ha), None) => {
+                    println!("[!] {} was deleted on {} but changed on {}, restoring", path, b.label, a.label);
+                    propagate(a, b, path, Some(&ha)).await?
+                }
+                (None, Some(hb)) => {
+                    println!("[!] {} was deleted on {} but changed on {}, restoring", path, a.label, b.label);
+                    propagate(b, a, path, Some(&hb)).await?
+                }
+                (None, None) => {}
+            },
+        }
+    }
+
+    if let Some(synced) = a.state.get(path).map(|e| e.hash) && base.as_ref() != Some(&synced) {
+        a.keep_base(path, &synced).await;
+    }
+    Ok(())
+}
+
+async fn pass<'a>(a: &mut Side<'a>, b: &mut Side<'a>) -> Result<()> {
+    a.refresh().await?;
+    b.refresh().await?;
+    let mut paths: BTreeSet<String> = a.files.keys().chain(b.files.keys()).cloned().collect();
+    paths.extend(a.state.paths());
+    paths.extend(b.state.paths());
+
+    for path in paths {
+        if a.ignore.is_ignored(&path) || b.ignore.is_ignored(&path) { continue; }
+        if let Err(e) = reconcile(a, b, &path).await {
+            eprintln!("[!] Failed to mirror {}: {:#}", path, e);
+        }
+    }
+    Ok(())
+}
+
+pub async fn run(first: &Pair, second: &Pair, config_path: &str) -> Result<()> {
+    if first.backend.is_read_only() && second.backend.is_read_only() {
+        return Err(anyhow!("Both mirror locations are read-only"));
+    }
+    let mut locations = [first.location.as_str(), second.location.as_str()];
+    locations.sort();
+    let key = format!("mirror:{}", &calculate_hash(locations.join("\n").as_bytes())[..16]);
+
+    let mut a = Side::open(first, config_path, &key)?;
+    let mut b = Side::open(second, config_path, &key)?;
+    println!("[*] Mirroring {} <-> {} ({}s interval)", a.label, b.label, MIRROR_INTERVAL.as_secs());
+
+    loop {
+        if let Err(e) = pass(&mut a, &mut b).await {
+            eprintln!("[!] Mirror pass failed: {:#}", e);
+        }
+        tokio::time::sleep(MIRROR_INTERVAL).await;
+    }
+}
@@ -0,0 +1,658 @@
+use crate::args::Location;
+use crate::backend::StorageBackend;
+use crate::config::PairConfig;
+use crate::crypto::Cipher;
+use crate::filter::{self, Filtered, IgnoreRules};
+use crate::merge::{MergeResult, PendingMerge};
+use crate::state::{self, Change, SyncState};
+use crate::transfer::{self, ChunkOutcome, Download, Downloads};
+use anyhow::{Result, Context, anyhow};
+use common::{FileMetadata, Message, Subscription};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use url::Url;
+
+pub enum LocalChange {
+    Modified { path: String, local: Option<FileMetadata> },
+    Removed(String),
+}
+
+impl LocalChange {
+    pub fn path(&self) -> &str {
+        match self {
+            LocalChange::Modified { path, .. } | LocalChange::Removed(path) => path,
+        }
+    }
+}
+
+pub fn print_history(path: &str, versions: &[FileMetadata]) {
+    if versions.is_empty() {
+        println!("[*] No history recorded for {}", path);
+        return;
+    }
+    println!("[*] History of {}:", path);
+    for v in versions {
+        let when = chrono::DateTime::from_timestamp(v.modified as i64, 0)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let by = v.last_modified_by.as_deref().unwrap_or("unknown");
+        if v.is_deleted {
+            println!("    v{:<5} {}  deleted by {}", v.version, when, by);
+        } else {
+            println!("    v{:<5} {}  {:>12} bytes  by {}", v.version, when, v.size, by);
+        }
+    }
+}
+
+pub fn scoped(storage_id: &str, cipher: Option<&Cipher>, msg: Message) -> Result<Message> {
+    let msg = match cipher {
+        Some(cipher) => cipher.seal_paths(msg)?,
+        None => msg,
+    };
+    Ok(Message::Scoped { storage_id: storage_id.to_string(), message: Box::new(msg) })
+}
+
+pub fn conflict_path(path: &str) -> String {
+    let p_obj = PathBuf::from(path);
+    let stem = p_obj.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = p_obj.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let ts = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+    p_obj.with_file_name(format!("{}_conflict_{}{}", stem, ts, ext))
+        .to_string_lossy()
+        .replace("\\", "/")
+}
+
+fn display_location(source: &str) -> String {
+    match Url::parse(source) {
+        Ok(mut url) if url.password().is_some() => {
+            let _ = url.set_password(None);
+            url.to_string()
+        }
+        _ => source.to_string(),
+    }
+}
+
+pub struct Pair {
+    pub location: String,
+    pub label: String,
+    pub storage_id: Option<String>,
+    pub subscription: Subscription,
+    pub backend: Arc<Box<dyn StorageBackend>>,
+    pub ignore: Arc<IgnoreRules>,
+    folder: Option<PathBuf>,
+    sync_state: Option<Arc<Mutex<SyncState>>>,
+    watching: bool,
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl Pair {
+    pub fn new(spec: PairConfig, source: &str, backend: Arc<Box<dyn StorageBackend>>) -> Self {
+        let folder = match Location::parse(source) {
+            Ok(Location::Folder(path)) => Some(path),
+            _ => None,
+        };
+        let ignore = Arc::new(IgnoreRules::default());
+        ignore.set_subscription(&spec.subscription);
+        Self {
+            location: spec.location,
+            label: display_location(source),
+            storage_id: spec.storage_id,
+            subscription: spec.subscription,
+            backend: Arc::new(Box::new(Filtered::new(backend, ignore.clone()))),
+            ignore,
+            folder,
+            sync_state: None,
+            watching: false,
+            _watcher: None,
+        }
+    }
+
+    pub fn config(&self) -> PairConfig {
+        PairConfig { location: self.location.clone(), storage_id: self.storage_id.clone(), subscription: self.subscription.clone() }
+    }
+
+    pub fn sync_state(&mut self, config_path: &str, storage_id: &str) -> Result<Arc<Mutex<SyncState>>> {
+        match &self.sync_state {
+            Some(state) => Ok(state.clone()),
+            None => Ok(self.sync_state.insert(Arc::new(Mutex::new(SyncState::open(config_path, storage_id)?))).clone()),
+        }
+    }
+
+    pub fn watch(&mut self, index: usize, changes_tx: &mpsc::UnboundedSender<(usize, LocalChange)>) -> Result<()> {
+        if self.watching || self.backend.is_read_only() {
+            return Ok(());
+        }
+        let Some(sync_state) = self.sync_state.clone() else { return Ok(()) };
+        self.watching = true;
+
+        if let Some(raw_path) = &self.folder {
+            let changes_w = changes_tx.clone();
+            let backend_w = self.backend.clone();
+            let ignore_w = self.ignore.clone();
+
+            let abs_root = std::fs::canonicalize(raw_path).unwrap_or_else(|_| raw_path.clone());
+            let (notify_tx, mut notify_rx) = mpsc::unbounded_channel();
+
+            let mut watcher = RecommendedWatcher::new(move |res: Result<Event, notify::Error>| {
+                if let Ok(event) = res { notify_tx.send(event).ok(); }
+            }, notify::Config::default()).context("Failed to create watcher")?;
+
+            if let Err(e) = watcher.watch(&abs_root, RecursiveMode::Recursive) {
+                eprintln!("[!] Watcher error: {}", e);
+            }
+            self._watcher = Some(watcher);
+
+            tokio::spawn(async move {
+                let to_relative = |sys_path: &Path| -> Option<String> {
+                     sys_path.strip_prefix(&abs_root).ok()
+                        .map(|p| p.to_string_lossy().replace("\\", "/"))
+                        .filter(|s| !s.is_empty())
+                };
+
+                while let Some(event) = notify_rx.recv().await {
+                    let mut paths = Vec::new();
+                    for rel in event.paths.iter().filter_map(|p| to_relative(p)) {
+                        if filter::is_ignore_file(&rel) {
+                            ignore_w.reload(&**backend_w, &rel).await;
+                        }
+                        if !ignore_w.is_ignored(&rel) { paths.push(rel); }
+                    }
+                    match event.kind {
+                        EventKind::Create(_) | EventKind::Modify(_) => {
+                            for rel in paths {
+                                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                                changes_w.send((index, LocalChange::Modified { path: rel, local: None })).ok();
+                            }
+                        }
+                        EventKind::Remove(_) => {
+                            for rel in paths {
+                                changes_w.send((index, LocalChange::Removed(rel))).ok();
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            });
+        } else {
+             println!("[*] [{}] Starting remote polling (10s interval)", self.label);
+             let changes_poll = changes_tx.clone();
+             let backend_poll = self.backend.clone();
+             let ignore_poll = self.ignore.clone();
+
+             tokio::spawn(async move {
+                 loop {
+                     tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+                     let Ok(files) = backend_poll.list_files().await else { continue };
+                     let present: HashSet<String> = files.iter().map(|f| f.path.clone()).collect();
+                     for file in files {
+                         let known = sync_state.lock().ok().and_then(|state| state.get(&file.path));
+                         if known.is_some_and(|e| e.matches(&file)) { continue; }
+                         changes_poll.send((index, LocalChange::Modified { path: file.path.clone(), local: Some(file) })).ok();
+                     }
+
+                     let known = sync_state.lock().map(|state| state.paths()).unwrap_or_default();
+                     for path in known.into_iter().filter(|p| !present.contains(p) && !ignore_poll.is_ignored(p)) {
+                         if let Ok(None) = backend_poll.stat(&path).await {
+                             changes_poll.send((index, LocalChange::Removed(path))).ok();
+                         }
+                     }
+                 }
+             });
+        }
+        Ok(())
+    }
+}
+
+pub struct Session {
+    pub storage_id: String,
+    pub label: String,
+    backend: Arc<Box<dyn StorageBackend>>,
+    ignore: Arc<IgnoreRules>,
+    sync_state: Arc<Mutex<SyncState>>,
+    tx: mpsc::UnboundedSender<WsMessage>,
+    cipher: Option<Arc<Cipher>>,
+    downloads: Downloads,
+    merges: HashMap<String, PendingMerge>,
+    remote_files: HashMap<String, FileMetadata>,
+}
+
+impl Session {
+    pub fn new(
+        pair: &Pair,
+        storage_id: String,
+        sync_state: Arc<Mutex<SyncState>>,
+        tx: mpsc::UnboundedSender<WsMessage>,
+        cipher: Option<Arc<Cipher>>,
+        files: &[FileMetadata],
+    ) -> Self {
+        Self {
+            downloads: Downloads::new(&storage_id, cipher.clone()),
+            storage_id,
+            label: pair.label.clone(),
+            backend: pair.backend.clone(),
+            ignore: pair.ignore.clone(),
+            sync_state,
+            tx,
+            cipher,
+            merges: HashMap::new(),
+            remote_files: files.iter().map(|f| (f.path.clone(), f.clone())).collect(),
+        }
+    }
+
+    pub fn send(&self, msg: Message) -> Result<()> {
+        let msg = scoped(&self.storage_id, self.cipher.as_deref(), msg)?;
+        self.tx.send(WsMessage::Text(serde_json::to_string(&msg)?)).map_err(|_| anyhow!("Channel closed"))
+    }
+
+    pub fn open(&self, msg: Message) -> Result<Message> {
+        match &self.cipher {
+            Some(cipher) => cipher.open_paths(msg),
+            None => Ok(msg),
+        }
+    }
+
+    async fn save_conflict_copy(&self, path: &str, content: &[u8]) {
+        let conflict_path = conflict_path(path);
+        match self.backend.write_file(&conflict_path, content).await {
+            Ok(_) => println!("[*] [{}] Saved conflict to {}", self.label, conflict_path),
+            Err(e) => eprintln!("[!] [{}] Failed to save conflict copy {}: {}", self.label, conflict_path, e),
+        }
+    }
+
+    async fn apply_outcome(&self, outcome: ChunkOutcome) -> Result<Option<Download>> {
+        match outcome {
+            ChunkOutcome::Continue(request) => self.send(request)?,
+            ChunkOutcome::Complete(download) if download.fetch => return Ok(Some(download)),
+            ChunkOutcome::Complete(download) => {
+                let path = download.meta.path.clone();
+                let version = download.meta.version;
+                let hash = download.meta.hash.clone();
+                if let Ok(mut state) = self.sync_state.lock() {
+                    state.expect(&path, &hash);
+                }
+                if let Err(e) = self.downloads.finish(download, &**self.backend).await {
+                    eprintln!("[!] [{}] Write error for {}: {:#}", self.label, path, e);
+                    if let Ok(mut state) = self.sync_state.lock() {
+                        state.clear_pending(&path);
+                    }
+                } else {
+                    println!("[v] [{}] Downloaded: {}", self.label, path);
+                    if filter::is_ignore_file(&path) {
+                        self.ignore.reload(&**self.backend, &path).await;
+                    }
+                    let local = self.backend.stat(&path).await.ok().flatten();
+                    if let Ok(mut state) = self.sync_state.lock() {
+                        state.record(&path, version, &hash, local.as_ref());
+                    }
+                    self.send(Message::Synced { path, version })?;
+                }
+            }
+            ChunkOutcome::Ignored => {}
+        }
+        Ok(None)
+    }
+
+    async fn download(&mut self, meta: FileMetadata) -> Result<()> {
+        let path = meta.path.clone();
+        match self.downloads.start(meta, &**self.backend).await {
+            Ok(outcome) => { self.apply_outcome(outcome).await?; }
+            Err(e) => eprintln!("[!] [{}] Failed to stage download {}: {}", self.label, path, e),
+        }
+        Ok(())
+    }
+
+    pub async fn reconcile(&mut self, initial_files: Vec<FileMetadata>) -> Result<()> {
+        let backend = self.backend.clone();
+        let sync_state = self.sync_state.clone();
+        let cipher = self.cipher.clone();
+
+        let Ok(local_files) = backend.list_files().await else { return Ok(()) };
+        println!("[*] [{}] Found {} local files", self.label, local_files.len());
+
+        let mut to_download = Vec::new();
+        for local in &local_files {
+            let remote = self.remote_files.get(&local.path);
+            let entry = sync_state.lock().ok().and_then(|state| state.get(&local.path));
+            let live_remote = remote.filter(|r| !r.is_deleted);
+
+            let digest = match &entry {
+                Some(e) if e.matches(local) => None,
+                _ => {
+                    let Ok(digest) = transfer::scan_file(&**backend, &local.path, cipher.as_deref()).await else { continue };
+                    if let Some(e) = &entry && e.hash == digest.hash && let Ok(mut state) = sync_state.lock() {
+                        state.touch(local);
+                    }
+                    Some(digest)
+                }
+            };
+            let local_hash = match (&digest, &entry) {
+                (Some(d), _) => d.hash.clone(),
+                (None, Some(e)) => e.hash.clone(),
+                (None, None) => continue,
+            };
+
+            match state::classify(entry.as_ref(), &local_hash, remote) {
+                Change::Unchanged => {
+                    if let Some(r) = live_remote {
+                        if let Ok(mut state) = sync_state.lock() {
+                            state.record(&r.path, r.version, &r.hash, Some(local));
+                        }
+                        self.send(Message::Synced { path: r.path.clone(), version: r.version })?;
+                    }
+                }
+                Change::LocalChanged => {
+                    let Some(digest) = digest else { continue };
+                    if let Ok(mut state) = sync_state.lock() {
+                        state.expect(&local.path, &digest.hash);
+                    }
+                    let target_version = match (&entry, live_remote) {
+                        (Some(e), Some(_)) => e.version + 1,
+                        _ => 0,
+                    };
+                    self.send(transfer::upload_header(&local.path, digest, target_version))?;
+                    println!("[^] [{}] Uploading: {}", self.label, local.path);
+                }
+                Change::RemoteChanged => match live_remote {
+                    Some(r) => to_download.push(r.clone()),
+                    None => {
+                        println!("[x] [{}] Deleted remotely while offline: {}", self.label, local.path);
+                        if let Ok(mut state) = sync_state.lock() {
+                            state.expect_delete(&local.path);
+                        }
+                        if let Err(e) = backend.delete_file(&local.path).await {
+                            eprintln!("[!] [{}] Failed to delete {}: {}", self.label, local.path, e);
+                        }
+                        if let Ok(mut state) = sync_state.lock() {
+                            state.forget(&local.path);
+                        }
+                    }
+                },
+                Change::BothChanged => match (&entry, live_remote, digest) {
+                    (_, _, None) => {}
+                    (Some(e), Some(_), Some(digest)) => {
+                        println!("[!] [{}] Changed both locally and remotely: {}", self.label, local.path);
+                        if let Ok(mut state) = sync_state.lock() {
+                            state.expect(&local.path, &digest.hash);
+                        }
+                        self.send(transfer::upload_header(&local.path, digest, e.version + 1))?;
+                    }
+                    (None, Some(r), _) => {
+                        let r = r.clone();
+                        println!("[!] [{}] {} differs from the server and was never synced here", self.label, local.path);
+                        if let Ok(content) = backend.read_file(&local.path).await {
+                            self.save_conflict_copy(&local.path, &content).await;
+                        }
+                        to_download.push(r);
+                    }
+                    (_, None, Some(digest)) => {
+                        if let Ok(mut state) = sync_state.lock() {
+                            state.expect(&local.path, &digest.hash);
+                        }
+                        self.send(transfer::upload_header(&local.path, digest, 0))?;
+                        println!("[^] [{}] Uploading: {}", self.label, local.path);
+                    }
+                },
+            }
+        }
+
+        let local_paths: HashSet<&str> = local_files.iter().map(|f| f.path.as_str()).collect();
+        let known_paths = sync_state.lock().map(|state| state.paths()).unwrap_or_default();
+        let mut vanished = HashSet::new();
+        for path in known_paths.into_iter().filter(|p| !local_paths.contains(p.as_str()) && !self.ignore.is_ignored(p)) {
+            let entry = sync_state.lock().ok().and_then(|state| state.get(&path));
+            let live_remote = self.remote_files.get_mut(&path).filter(|r| !r.is_deleted);
+            match (entry, live_remote) {
+                (Some(e), Some(r)) if r.version == e.version => {
+                    println!("[x] [{}] Deleted locally while offline: {}", self.label, path);
+                    r.is_deleted = true;
+                    self.send(Message::DeleteFile { path: path.clone() })?;
+                    vanished.insert(path.clone());
+                }
+                (_, Some(_)) => {
+                    println!("[!] [{}] {} was deleted locally but changed remotely, restoring", self.label, path);
+                    continue;
+                }
+                (_, None) => {}
+            }
+            if let Ok(mut state) = sync_state.lock() {
+                state.forget(&path);
+            }
+        }
+
+        for remote in &initial_files {
+            if !remote.is_deleted && !local_paths.contains(remote.path.as_str()) && !vanished.contains(&remote.path) && !self.ignore.is_ignored(&remote.path) {
+                to_download.push(remote.clone());
+            }
+        }
+
+        if !backend.is_read_only() {
+            for remote in to_download {
+                println!("[v] [{}] Requesting download: {}", self.label, remote.path);
+                self.download(remote).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn handle_local_change(&self, change: LocalChange) -> Result<()> {
+        match change {
+            LocalChange::Modified { path, local } => {
+                let Ok(digest) = transfer::scan_file(&**self.backend, &path, self.cipher.as_deref()).await else { return Ok(()) };
+                let known = self.sync_state.lock().ok().and_then(|state| state.get(&path));
+                let should_upload = match self.sync_state.lock() {
+                    Ok(mut state) => {
+                        if state.local_hash(&path).as_deref() == Some(digest.hash.as_str()) {
+                            false
+                        } else {
+                            state.expect(&path, &digest.hash);
+                            true
+                        }
+                    }
+                    Err(_) => {
+                        eprintln!("[!] Mutex poisoned");
+                        false
+                    }
+                };
+
+                if !should_upload {
+                    if known.is_some_and(|e| e.hash == digest.hash) {
+                        let local = match local {
+                            Some(local) => Some(local),
+                            None => self.backend.stat(&path).await.ok().flatten(),
+                        };
+                        if let Some(local) = local && let Ok(mut state) = self.sync_state.lock() {
+                            state.touch(&local);
+                        }
+                    }
+                    return Ok(());
+                }
+
+                self.send(transfer::upload_header(&path, digest, 0))?;
+                println!("[^] [{}] Uploading: {}", self.label, path);
+            }
+            LocalChange::Removed(path) => {
+                let known = match self.sync_state.lock() {
+                    Ok(mut state) => {
+                        if state.take_expected_delete(&path) { return Ok(()); }
+                        let known = state.local_hash(&path).is_some();
+                        state.forget(&path);
+                        known
+                    }
+                    Err(_) => false,
+                };
+                if known {
+                    self.send(Message::DeleteFile { path: path.clone() })?;
+                    println!("[x] [{}] Deleting: {}", self.label, path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn handle_message(&mut self, msg: Message) -> Result<()> {
+        let mut fetched = Vec::new();
+        match msg {
+            Message::FileUpdate { meta } => {
+                self.remote_files.insert(meta.path.clone(), meta.clone());
+                if meta.is_deleted || self.ignore.is_ignored(&meta.path) { return Ok(()); }
+
+                let is_synced = self.sync_state.lock()
+                    .map(|state| state.local_hash(&meta.path).as_deref() == Some(meta.hash.as_str()))
+                    .unwrap_or(false);
+                if is_synced {
+                    let local = self.backend.stat(&meta.path).await.ok().flatten();
+                    if let Ok(mut state) = self.sync_state.lock() {
+                        state.record(&meta.path, meta.version, &meta.hash, local.as_ref());
+                    }
+                    return Ok(());
+                }
+
+                if self.backend.is_read_only() {
+                    println!("[!] [{}] Skipped update for read-only backend: {}", self.label, meta.path);
+                } else {
+                    println!("[v] [{}] Downloading: {}", self.label, meta.path);
+                    self.download(meta).await?;
+                }
+            }
+            Message::RequestChunks { path, chunks } => {
+                for chunk in chunks {
+                    match transfer::read_chunk(&**self.backend, &path, &chunk, self.cipher.as_deref()).await {
+                        Ok(Some(data)) => {
+                            self.send(transfer::chunk_header(&path, chunk.offset, &data))?;
+                            self.tx.send(WsMessage::Binary(data)).map_err(|_| anyhow!("Channel closed"))?;
+                        }
+                        Ok(None) => eprintln!("[!] [{}] {} changed during upload, waiting for next change", self.label, path),
+                        Err(e) => eprintln!("[!] [{}] Read error for {}: {}", self.label, path, e),
+                    }
+                }
+            }
+            Message::VersionList { path, versions } => print_history(&path, &versions),
+            Message::Error { message } => eprintln!("[!] [{}] Server Error: {}", self.label, message),
+            Message::DeleteFile { path } => {
+                if let Some(meta) = self.remote_files.get_mut(&path) {
+                    meta.is_deleted = true;
+                }
+                self.downloads.cancel(&path);
+                if !self.backend.is_read_only() && !self.ignore.is_ignored(&path) {
+                    println!("[x] [{}] Remote delete: {}", self.label, path);
+                    if let Ok(mut state) = self.sync_state.lock() {
+                        state.expect_delete(&path);
+                    }
+                    let _ = self.backend.delete_file(&path).await;
+                    if let Ok(mut state) = self.sync_state.lock() {
+                        state.forget(&path);
+                    }
+                    if filter::is_ignore_file(&path) {
+                        self.ignore.reload(&**self.backend, &path).await;
+                    }
+                }
+            }
+            Message::IgnorePatternsUpdated { patterns, .. } => {
+                println!("[*] [{}] Server ignore patterns updated", self.label);
+                self.ignore.set_patterns(&patterns);
+            }
+            Message::ConflictDetected { path, server_version, base } => {
+                println!("[!] [{}] Conflict detected: {} (v{}).", self.label, path, server_version);
+                self.downloads.cancel_fetches(&path);
+                self.merges.remove(&path);
+
+                let current = self.remote_files.get(&path).filter(|m| !m.is_deleted).cloned();
+                let local = match self.backend.read_file(&path).await {
+                    Ok(content) => content,
+                    Err(e) => {
+                        eprintln!("[!] [{}] Read error for {}: {}", self.label, path, e);
+                        return Ok(());
+                    }
+                };
+                let pending = match (&current, &base) {
+                    (Some(current), Some(base)) if current.version == server_version => {
+                        PendingMerge::new(current.clone(), base, local.clone())
+                    }
+                    _ => None,
+                };
+
+                match (pending, base) {
+                    (Some(merge), Some(base)) => {
+                        println!("[*] [{}] Fetching v{} and v{} of {} for merge", self.label, base.version, server_version, path);
+                        let current = merge.current.clone();
+                        self.merges.insert(path.clone(), merge);
+                        for meta in [base, current] {
+                            match self.downloads.fetch(meta, &**self.backend).await {
+                                Ok(outcome) => fetched.extend(self.apply_outcome(outcome).await?),
+                                Err(e) => eprintln!("[!] [{}] Failed to fetch {}: {}", self.label, path, e),
+                            }
+                        }
+                    }
+                    _ => {
+                        self.save_conflict_copy(&path, &local).await;
+                        if let Some(meta) = current {
+                            self.download(meta).await?;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        self.complete_merges(fetched).await
+    }
+
+    pub async fn on_chunk(&mut self, path: &str, hash: &str, data: &[u8]) -> Result<()> {
+        let mut fetched = Vec::new();
+        match self.downloads.on_chunk(path, hash, data).await {
+            Ok(outcomes) => {
+                for outcome in outcomes {
+                    fetched.extend(self.apply_outcome(outcome).await?);
+                }
+            }
+            Err(e) => eprintln!("[!] [{}] Failed to store chunk of {}: {}", self.label, path, e),
+        }
+        self.complete_merges(fetched).await
+    }
+
+    async fn complete_merges(&mut self, fetched: Vec<Download>) -> Result<()> {
+        for download in fetched {
+            let path = download.meta.path.clone();
+            let version = download.meta.version;
+            let Some(merge) = self.merges.get_mut(&path) else { continue };
+
+            let accepted = match self.downloads.read(download).await {
+                Ok(data) => merge.accept(version, data).is_ok(),
+                Err(e) => {
+                    eprintln!("[!] [{}] Failed to read v{} of {}: {}", self.label, version, path, e);
+                    false
+                }
+            };
+            if accepted && !merge.is_ready() { continue; }
+
+            let Some(merge) = self.merges.remove(&path) else { continue };
+            let current = merge.current.clone();
+            let outcome = if accepted { merge.merge() } else { MergeResult::Conflicted(merge.local().to_string()) };
+
+            match outcome {
+                MergeResult::Clean(merged) => {
+                    let digest = transfer::digest_bytes(merged.as_bytes(), self.cipher.as_deref())?;
+                    if let Ok(mut state) = self.sync_state.lock() {
+                        state.expect(&path, &digest.hash);
+                    }
+                    if let Err(e) = self.backend.write_file(&path, merged.as_bytes()).await {
+                        eprintln!("[!] [{}] Write error for {}: {:#}", self.label, path, e);
+                        continue;
+                    }
+                    println!("[*] [{}] Merged v{} into local changes of {}", self.label, current.version, path);
+                    self.send(transfer::upload_header(&path, digest, current.version + 1))?;
+                    println!("[^] [{}] Uploading: {}", self.label, path);
+                }
+                MergeResult::Conflicted(marked) => {
+                    println!("[!] [{}] Merge of {} has overlapping changes", self.label, path);
+                    self.save_conflict_copy(&path, marked.as_bytes()).await;
+                    self.download(current).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
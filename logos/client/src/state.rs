@@ -0,0 +1,208 @@
+use anyhow::{Context, Result};
+use common::FileMetadata;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncedEntry {
+    pub version: u64,
+    pub hash: String,
+    pub size: u64,
+    pub mtime: u64,
+}
+
+impl SyncedEntry {
+    pub fn matches(&self, local: &FileMetadata) -> bool {
+        self.mtime != 0 && self.size == local.size && self.mtime == local.modified
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    Unchanged,
+    LocalChanged,
+    RemoteChanged,
+    BothChanged,
+}
+
+pub struct SyncState {
+    conn: Connection,
+    storage_id: String,
+    pending: HashMap<String, String>,
+    expected_deletes: HashSet<String>,
+}
+
+impl SyncState {
+    pub fn open(config_path: &str, storage_id: &str) -> Result<Self> {
+        let config = Path::new(config_path);
+        let stem = config.file_stem().unwrap_or_default().to_string_lossy();
+        let file = config.with_file_name(format!("{}.db", stem));
+
+        let conn = Connection::open(&file)
+            .with_context(|| format!("Failed to open sync database {}", file.display()))?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS files (
+                storage_id TEXT NOT NULL,
+                path TEXT NOT NULL,
+                version INTEGER NOT NULL,
+                hash TEXT NOT NULL,
+                size INTEGER NOT NULL DEFAULT 0,
+                mtime INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (storage_id, path)
+             );
+             CREATE TABLE IF NOT EXISTS bases (
+                storage_id TEXT NOT NULL,
+                path TEXT NOT NULL,
+                hash TEXT NOT NULL,
+                content TEXT NOT NULL,
+                PRIMARY KEY (storage_id, path)
+             );",
+        ).context("Failed to initialize sync database")?;
+
+        Ok(Self {
+            conn,
+            storage_id: storage_id.to_string(),
+            pending: HashMap::new(),
+            expected_deletes: HashSet::new(),
+        })
+    }
+
+    pub fn get(&self, path: &str) -> Option<SyncedEntry> {
+        self.conn.query_row(
+            "SELECT version, hash, size, mtime FROM files WHERE storage_id = ?1 AND path = ?2",
+            params![self.storage_id, path],
+            |row| Ok(SyncedEntry {
+                version: row.get::<_, i64>(0)? as u64,
+                hash: row.get(1)?,
+                size: row.get::<_, i64>(2)? as u64,
+                mtime: row.get::<_, i64>(3)? as u64,
+            }),
+        ).optional().unwrap_or_else(|e| {
+            eprintln!("[!] Failed to read sync state for {}: {}", path, e);
+            None
+        })
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        let result = self.conn
+            .prepare("SELECT path FROM files WHERE storage_id = ?1")
+            .and_then(|mut stmt| {
+                stmt.query_map(params![self.storage_id], |row| row.get(0))?
+                    .collect::<rusqlite::Result<Vec<String>>>()
+            });
+        result.unwrap_or_else(|e| {
+            eprintln!("[!] Failed to read sync state: {}", e);
+            Vec::new()
+        })
+    }
+
+    pub fn local_hash(&self, path: &str) -> Option<String> {
+        self.pending.get(path).cloned().or_else(|| self.get(path).map(|e| e.hash))
+    }
+
+    pub fn expect(&mut self, path: &str, hash: &str) {
+        self.pending.insert(path.to_string(), hash.to_string());
+    }
+
+    pub fn clear_pending(&mut self, path: &str) {
+        self.pending.remove(path);
+    }
+
+    pub fn record(&mut self, path: &str, version: u64, hash: &str, local: Option<&FileMetadata>) {
+        if self.pending.get(path).is_some_and(|h| h == hash) {
+            self.pending.remove(path);
+        }
+        let (size, mtime) = local.map(|l| (l.size, l.modified)).unwrap_or_default();
+        let result = self.conn.transaction().and_then(|tx| {
+            tx.execute(
+                "INSERT INTO files (storage_id, path, version, hash, size, mtime) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT (storage_id, path) DO UPDATE SET version = ?3, hash = ?4, size = ?5, mtime = ?6",
+                params![self.storage_id, path, version as i64, hash, size as i64, mtime as i64],
+            )?;
+            tx.commit()
+        });
+        if let Err(e) = result {
+            eprintln!("[!] Failed to save sync state for {}: {}", path, e);
+        }
+    }
+
+    pub fn touch(&mut self, local: &FileMetadata) {
+        if let Err(e) = self.conn.execute(
+            "UPDATE files SET size = ?3, mtime = ?4 WHERE storage_id = ?1 AND path = ?2",
+            params![self.storage_id, local.path, local.size as i64, local.modified as i64],
+        ) {
+            eprintln!("[!] Failed to save sync state for {}: {}", local.path, e);
+        }
+    }
+
+    pub fn forget(&mut self, path: &str) {
+        self.pending.remove(path);
+        if let Err(e) = self.conn.execute(
+            "DELETE FROM files WHERE storage_id = ?1 AND path = ?2",
+            params![self.storage_id, path],
+        ) {
+            eprintln!("[!] Failed to save sync state for {}: {}", path, e);
+        }
+        self.forget_base(path);
+    }
+
+    pub fn base(&self, path: &str, hash: &str) -> Option<String> {
+        self.conn.query_row(
+            "SELECT content FROM bases WHERE storage_id = ?1 AND path = ?2 AND hash = ?3",
+            params![self.storage_id, path, hash],
+            |row| row.get(0),
+        ).optional().unwrap_or_else(|e| {
+            eprintln!("[!] Failed to read merge base for {}: {}", path, e);
+            None
+        })
+    }
+
+    pub fn save_base(&mut self, path: &str, hash: &str, content: &str) {
+        if let Err(e) = self.conn.execute(
+            "INSERT INTO bases (storage_id, path, hash, content) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (storage_id, path) DO UPDATE SET hash = ?3, content = ?4",
+            params![self.storage_id, path, hash, content],
+        ) {
+            eprintln!("[!] Failed to save merge base for {}: {}", path, e);
+        }
+    }
+
+    pub fn forget_base(&mut self, path: &str) {
+        if let Err(e) = self.conn.execute(
+            "DELETE FROM bases WHERE storage_id = ?1 AND path = ?2",
+            params![self.storage_id, path],
+        ) {
+            eprintln!("[!] Failed to save merge base for {}: {}", path, e);
+        }
+    }
+
+    pub fn expect_delete(&mut self, path: &str) {
+        self.expected_deletes.insert(path.to_string());
+    }
+
+    pub fn take_expected_delete(&mut self, path: &str) -> bool {
+        self.expected_deletes.remove(path)
+    }
+}
+
+pub fn classify(entry: Option<&SyncedEntry>, local_hash: &str, remote: Option<&FileMetadata>) -> Change {
+    let remote = remote.filter(|r| !r.is_deleted || entry.is_some());
+    match (entry, remote) {
+        (None, None) => Change::LocalChanged,
+        (None, Some(r)) if r.hash == local_hash => Change::Unchanged,
+        (None, Some(_)) => Change::BothChanged,
+        (Some(e), remote) => {
+            let local_changed = e.hash != local_hash;
+            let remote_changed = remote.is_none_or(|r| r.version != e.version);
+            match (local_changed, remote_changed) {
+                (false, false) => Change::Unchanged,
+                (true, false) => Change::LocalChanged,
+                (false, true) => Change::RemoteChanged,
+                (true, true) if remote.is_some_and(|r| !r.is_deleted && r.hash == local_hash) => Change::Unchanged,
+                (true, true) => Change::BothChanged,
+            }
+        }
+    }
+}
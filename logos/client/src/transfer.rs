@@ -0,0 +1,274 @@
+use crate::backend::StorageBackend;
+use crate::crypto::Cipher;
+use anyhow::{Result, anyhow};
+use common::{CHUNK_SIZE, CHUNK_WINDOW, ChunkInfo, Chunker, ContentHasher, FileDigest, FileMetadata, Message, calculate_hash};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+pub struct Download {
+    pub meta: FileMetadata,
+    pub fetch: bool,
+    missing: VecDeque<ChunkInfo>,
+    requested: Vec<ChunkInfo>,
+}
+
+impl Download {
+    fn key(&self) -> String {
+        if self.fetch {
+            format!("{}@{}", self.meta.path, self.meta.version)
+        } else {
+            self.meta.path.clone()
+        }
+    }
+}
+
+pub enum ChunkOutcome {
+    Continue(Message),
+    Complete(Download),
+    Ignored,
+}
+
+pub struct Downloads {
+    dir: PathBuf,
+    active: HashMap<String, Download>,
+    cipher: Option<Arc<Cipher>>,
+}
+
+impl Downloads {
+    pub fn new(storage_id: &str, cipher: Option<Arc<Cipher>>) -> Self {
+        Self {
+            dir: std::env::temp_dir().join("logos").join(storage_id),
+            active: HashMap::new(),
+            cipher,
+        }
+    }
+
+    fn chunk_path(&self, hash: &str) -> PathBuf {
+        self.dir.join("chunks").join(hash)
+    }
+
+    pub async fn start(&mut self, meta: FileMetadata, backend: &dyn StorageBackend) -> Result<ChunkOutcome> {
+        self.begin(meta, backend, false).await
+    }
+
+    pub async fn fetch(&mut self, meta: FileMetadata, backend: &dyn StorageBackend) -> Result<ChunkOutcome> {
+        self.begin(meta, backend, true).await
+    }
+
+    async fn begin(&mut self, meta: FileMetadata, backend: &dyn StorageBackend, fetch: bool) -> Result<ChunkOutcome> {
+        fs::create_dir_all(self.dir.join("chunks")).await?;
+
+        let cipher = self.cipher.as_deref();
+        let local = scan_file(backend, &meta.path, cipher).await.map(|d| d.chunks).unwrap_or_default();
+        let local_by_hash: HashMap<&str, &ChunkInfo> = local.iter().map(|c| (c.hash.as_str(), c)).collect();
+
+        let mut missing = VecDeque::new();
+        let mut seen = HashSet::new();
+        let mut reused = 0;
+        for chunk in &meta.chunks {
+            if !seen.insert(chunk.hash.clone()) { continue; }
+
+            let staged = self.chunk_path(&chunk.hash);
+            if fs::metadata(&staged).await.is_ok() {
+                reused += 1;
+                continue;
+            }
+
+            if let Some(existing) = local_by_hash.get(chunk.hash.as_str())
+                && let Ok(Some(data)) = read_chunk(backend, &meta.path, existing, cipher).await {
+                    fs::write(&staged, data).await?;
+                    reused += 1;
+                    continue;
+                }
+            missing.push_back(chunk.clone());
+        }
+
+        if reused > 0 && !fetch {
+            println!("[*] Reusing {} of {} chunks for {}", reused, seen.len(), meta.path);
+        }
+
+        let download = Download { meta, fetch, missing, requested: Vec::new() };
+        self.active.remove(&download.key());
+        Ok(self.advance(download))
+    }
+
+    fn advance(&mut self, mut download: Download) -> ChunkOutcome {
+        if !download.requested.is_empty() {
+            self.active.insert(download.key(), download);
+            return ChunkOutcome::Ignored;
+        }
+
+        if download.missing.is_empty() {
+            return ChunkOutcome::Complete(download);
+        }
+
+        let window = download.missing.len().min(CHUNK_WINDOW);
+        download.requested = download.missing.drain(..window).collect();
+        let request = Message::RequestChunks { path: download.meta.path.clone(), chunks: download.requested.clone() };
+        self.active.insert(download.key(), download);
+        ChunkOutcome::Continue(request)
+    }
+
+    pub async fn on_chunk(&mut self, path: &str, hash: &str, data: &[u8]) -> Result<Vec<ChunkOutcome>> {
+        let waiting: Vec<String> = self.active.iter()
+            .filter(|(_, d)| d.meta.path == path && d.requested.iter().any(|c| c.hash == hash))
+            .map(|(key, _)| key.clone())
+            .collect();
+        if waiting.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let valid = calculate_hash(data) == hash;
+        if valid {
+            fs::write(self.chunk_path(hash), data).await?;
+        }
+
+        let mut outcomes = Vec::new();
+        for key in waiting {
+            let Some(mut download) = self.active.remove(&key) else { continue };
+            if let Some(pos) = download.requested.iter().position(|c| c.hash == hash) {
+                let chunk = download.requested.remove(pos);
+                if !valid {
+                    download.missing.push_back(chunk);
+                }
+            }
+            outcomes.push(self.advance(download));
+        }
+        Ok(outcomes)
+    }
+
+    pub async fn read(&self, download: Download) -> Result<Vec<u8>> {
+        let meta = download.meta;
+        let mut content = Vec::with_capacity(meta.size as usize);
+        let mut hasher = ContentHasher::new();
+        for chunk in &meta.chunks {
+            let data = fs::read(self.chunk_path(&chunk.hash)).await?;
+            hasher.update(&data);
+            match &self.cipher {
+                Some(cipher) => content.extend_from_slice(&cipher.open(&data)?),
+                None => content.extend_from_slice(&data),
+            }
+        }
+        self.release_chunks(&meta).await;
+
+        if hasher.finish() != meta.hash {
+            return Err(anyhow!("Hash mismatch for {} v{}", meta.path, meta.version));
+        }
+        Ok(content)
+    }
+
+    async fn release_chunks(&self, meta: &FileMetadata) {
+        let still_needed: HashSet<&str> = self.active.values()
+            .flat_map(|d| d.meta.chunks.iter().map(|c| c.hash.as_str()))
+            .collect();
+        for chunk in &meta.chunks {
+            if !still_needed.contains(chunk.hash.as_str()) {
+                let _ = fs::remove_file(self.chunk_path(&chunk.hash)).await;
+            }
+        }
+    }
+
+    pub async fn finish(&self, download: Download, backend: &dyn StorageBackend) -> Result<()> {
+        let meta = download.meta;
+        let key = calculate_hash(format!("{}:{}", meta.path, meta.hash).as_bytes());
+        let part = self.dir.join(format!("{}.part", key));
+
+        let mut file = fs::File::create(&part).await?;
+        let mut hasher = ContentHasher::new();
+        for chunk in &meta.chunks {
+            let data = fs::read(self.chunk_path(&chunk.hash)).await?;
+            hasher.update(&data);
+            match &self.cipher {
+                Some(cipher) => file.write_all(&cipher.open(&data)?).await?,
+                None => file.write_all(&data).await?,
+            }
+        }
+        file.flush().await?;
+        drop(file);
+
+        let result = if hasher.finish() != meta.hash {
+            Err(anyhow!("Hash mismatch for {}", meta.path))
+        } else {
+            backend.write_from(&meta.path, &part).await
+        };
+        let _ = fs::remove_file(&part).await;
+
+        self.release_chunks(&meta).await;
+        result
+    }
+
+    pub fn cancel(&mut self, path: &str) {
+        self.active.remove(path);
+    }
+
+    pub fn cancel_fetches(&mut self, path: &str) {
+        self.active.retain(|_, d| !(d.fetch && d.meta.path == path));
+    }
+}
+
+pub fn digest_bytes(data: &[u8], cipher: Option<&Cipher>) -> Result<FileDigest> {
+    let mut chunker = Chunker::new();
+    chunker.update(data);
+    let mut digest = chunker.finish();
+
+    if let Some(cipher) = cipher {
+        let mut hasher = ContentHasher::new();
+        for chunk in &mut digest.chunks {
+            let start = chunk.offset as usize;
+            let sealed = cipher.seal(&data[start..start + chunk.size as usize])?;
+            hasher.update(&sealed);
+            chunk.hash = calculate_hash(&sealed);
+        }
+        digest.hash = hasher.finish();
+    }
+    Ok(digest)
+}
+
+pub async fn scan_file(backend: &dyn StorageBackend, path: &str, cipher: Option<&Cipher>) -> Result<FileDigest> {
+    let mut chunker = Chunker::new();
+    let mut offset = 0u64;
+    loop {
+        let data = backend.read_range(path, offset, CHUNK_SIZE).await?;
+        chunker.update(&data);
+        offset += data.len() as u64;
+        if data.len() < CHUNK_SIZE { break; }
+    }
+    let mut digest = chunker.finish();
+
+    if let Some(cipher) = cipher {
+        let mut hasher = ContentHasher::new();
+        for chunk in &mut digest.chunks {
+            let sealed = cipher.seal(&backend.read_range(path, chunk.offset, chunk.size as usize).await?)?;
+            hasher.update(&sealed);
+            chunk.hash = calculate_hash(&sealed);
+        }
+        digest.hash = hasher.finish();
+    }
+    Ok(digest)
+}
+
+pub async fn read_chunk(backend: &dyn StorageBackend, path: &str, chunk: &ChunkInfo, cipher: Option<&Cipher>) -> Result<Option<Vec<u8>>> {
+    let data = backend.read_range(path, chunk.offset, chunk.size as usize).await?;
+    let data = match cipher {
+        Some(cipher) => cipher.seal(&data)?,
+        None => data,
+    };
+    Ok((calculate_hash(&data) == chunk.hash).then_some(data))
+}
+
+pub fn upload_header(path: &str, digest: FileDigest, target_version: u64) -> Message {
+    Message::StartTransfer {
+        path: path.to_string(),
+        size: digest.size,
+        target_version,
+        hash: digest.hash,
+        chunks: digest.chunks,
+    }
+}
+
+pub fn chunk_header(path: &str, offset: u64, data: &[u8]) -> Message {
+    Message::TransferChunk { path: path.to_string(), offset, hash: calculate_hash(data) }
+}
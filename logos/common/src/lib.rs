@@ -1,4 +1,35 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+
+pub const MIN_CHUNK_SIZE: usize = 256 * 1024;
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+pub const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+pub const CHUNK_WINDOW: usize = 4;
+
+const CUT_MASK: u64 = !0 << 44;
+
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut seed = 0x9E37_79B9_7F4A_7C15u64;
+    let mut i = 0;
+    while i < 256 {
+        seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChunkInfo {
+    pub hash: String,
+    pub offset: u64,
+    pub size: u64,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct FileMetadata {
@@ -9,12 +40,51 @@ pub struct FileMetadata {
     pub hash: String,
     pub is_deleted: bool,
     pub last_modified_by: Option<String>,
+    #[serde(default)]
+    pub chunks: Vec<ChunkInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EncryptionInfo {
+    pub salt: String,
+    pub key_check: String,
+    #[serde(default)]
+    pub encrypt_paths: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StorageInfo {
     pub id: String,
     pub name: String,
+    #[serde(default)]
+    pub encryption: Option<EncryptionInfo>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Read => "read",
+            Role::Write => "write",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(input: &str) -> Option<Self> {
+        match input {
+            "read" => Some(Role::Read),
+            "write" => Some(Role::Write),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,24 +94,103 @@ pub struct ClientInfo {
     pub storage_id: String,
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Subscription {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+impl Subscription {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn covers(&self, path: &str) -> bool {
+        let under = |prefix: &String| {
+            let prefix = prefix.trim_matches('/');
+            prefix.is_empty() || path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        };
+        (self.include.is_empty() || self.include.iter().any(under)) && !self.exclude.iter().any(under)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Message {
+    Authenticate { token: String },
+    Authenticated { user: String },
+    CreateUser {
+        name: String,
+        #[serde(default)]
+        admin: bool,
+    },
+    UserCreated { name: String, token: String },
+    SetRole { storage_id: String, user: String, role: Option<Role> },
+    RoleUpdated { storage_id: String, user: String, role: Option<Role> },
+    SetIgnorePatterns { storage_id: String, patterns: Vec<String> },
+    IgnorePatternsUpdated { storage_id: String, patterns: Vec<String> },
+
     Register { client_id: String },
     RegisterDashboard, 
 
     RequestStorageList,
     StorageList { storages: Vec<StorageInfo> },
-    CreateStorage { name: String },
+    CreateStorage {
+        name: String,
+        #[serde(default)]
+        encryption: Option<EncryptionInfo>,
+    },
     DeleteStorage { storage_id: String },
-    JoinStorage { storage_id: String, client_name: String },
+    JoinStorage {
+        storage_id: String,
+        client_name: String,
+        #[serde(default)]
+        device_id: Option<String>,
+        #[serde(default)]
+        multiplex: bool,
+        #[serde(default)]
+        subscription: Subscription,
+    },
     
-    Welcome { storage_id: String, files: Vec<FileMetadata> },
+    Welcome {
+        storage_id: String,
+        files: Vec<FileMetadata>,
+        #[serde(default)]
+        encryption: Option<EncryptionInfo>,
+        #[serde(default)]
+        ignore: Vec<String>,
+    },
     FileUpdate { meta: FileMetadata },
-    StartTransfer { path: String, size: u64, target_version: u64 },
-    RequestFile { path: String },
+    StartTransfer {
+        path: String,
+        size: u64,
+        target_version: u64,
+        #[serde(default)]
+        hash: String,
+        #[serde(default)]
+        chunks: Vec<ChunkInfo>,
+    },
+    RequestChunks { path: String, chunks: Vec<ChunkInfo> },
+    TransferChunk { path: String, offset: u64, hash: String },
+    RequestFile {
+        path: String,
+        #[serde(default)]
+        offset: u64,
+    },
     DeleteFile { path: String },
-    ConflictDetected { path: String, server_version: u64 },
-    Error { message: String }
+    ListVersions { path: String },
+    VersionList { path: String, versions: Vec<FileMetadata> },
+    RestoreVersion { path: String, version: u64 },
+    Synced { path: String, version: u64 },
+    ConflictDetected {
+        path: String,
+        server_version: u64,
+        #[serde(default)]
+        base: Option<FileMetadata>,
+    },
+    Error { message: String },
+    Scoped { storage_id: String, message: Box<Message> },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -59,9 +208,121 @@ pub enum DashboardMessage {
     }
 }
 
+#[derive(Default)]
+pub struct ContentHasher {
+    inner: Sha256,
+}
+
+impl ContentHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn finish(self) -> String {
+        hex::encode(self.inner.finalize())
+    }
+}
+
 pub fn calculate_hash(content: &[u8]) -> String {
-    use sha2::{Sha256, Digest};
-    let mut hasher = Sha256::new();
+    let mut hasher = ContentHasher::new();
     hasher.update(content);
-    hex::encode(hasher.finalize())
-}
\ No newline at end of file
+    hasher.finish()
+}
+
+pub fn hash_reader<R: Read>(mut reader: R) -> std::io::Result<String> {
+    let mut hasher = ContentHasher::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 { break; }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+pub struct FileDigest {
+    pub size: u64,
+    pub hash: String,
+    pub chunks: Vec<ChunkInfo>,
+}
+
+#[derive(Default)]
+pub struct Chunker {
+    chunks: Vec<ChunkInfo>,
+    file_hasher: ContentHasher,
+    chunk_hasher: ContentHasher,
+    fingerprint: u64,
+    offset: u64,
+    len: usize,
+}
+
+impl Chunker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.file_hasher.update(data);
+        while !data.is_empty() {
+            let mut cut = None;
+            for (i, &b) in data.iter().enumerate() {
+                self.len += 1;
+                self.fingerprint = (self.fingerprint << 1).wrapping_add(GEAR[b as usize]);
+                if self.len >= MAX_CHUNK_SIZE || (self.len >= MIN_CHUNK_SIZE && self.fingerprint & CUT_MASK == 0) {
+                    cut = Some(i + 1);
+                    break;
+                }
+            }
+
+            match cut {
+                Some(n) => {
+                    self.chunk_hasher.update(&data[..n]);
+                    self.emit();
+                    data = &data[n..];
+                }
+                None => {
+                    self.chunk_hasher.update(data);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn emit(&mut self) {
+        let hasher = std::mem::take(&mut self.chunk_hasher);
+        self.chunks.push(ChunkInfo {
+            hash: hasher.finish(),
+            offset: self.offset,
+            size: self.len as u64,
+        });
+        self.offset += self.len as u64;
+        self.len = 0;
+        self.fingerprint = 0;
+    }
+
+    pub fn finish(mut self) -> FileDigest {
+        if self.len > 0 {
+            self.emit();
+        }
+        FileDigest {
+            size: self.offset,
+            hash: self.file_hasher.finish(),
+            chunks: self.chunks,
+        }
+    }
+}
+
+pub fn digest_reader<R: Read>(mut reader: R) -> std::io::Result<FileDigest> {
+    let mut chunker = Chunker::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 { break; }
+        chunker.update(&buf[..n]);
+    }
+    Ok(chunker.finish())
+}
@@ -5,6 +5,12 @@ export interface StorageInfo {
   name: string;
 }
 
+export interface ChunkInfo {
+  hash: string;
+  offset: number;
+  size: number;
+}
+
 export interface FileMetadata {
   path: string;
   size: number;
@@ -13,6 +19,7 @@ export interface FileMetadata {
   hash: string;
   is_deleted: boolean;
   last_modified_by?: string;
+  chunks?: ChunkInfo[];
 }
 
 export interface ActivityEntry {
@@ -41,10 +48,11 @@ export interface Stats {
 export class Logos {
   private socket: WebSocket | null = null;
   
-  private downloadQueue = new Set<string>();
-  private nextBinaryMetadata: { path: string } | null = null;
+  private downloads = new Map<string, { size: number; received: number; parts: Blob[] }>();
+  private nextBinaryMetadata: { path: string; offset: number } | null = null;
 
   readonly WEBSOCKET_URL = 'ws://localhost:3000/ws/client';
+  readonly TOKEN_KEY = 'logos_token';
 
   storages = signal<StorageInfo[]>([]);
   activeStorageId = signal<string | null>(null);
@@ -63,8 +71,7 @@ export class Logos {
     this.socket.onopen = () => {
       this.isConnected.set(true);
       this.addActivity('connect', 'Dashboard connected', 'System');
-      this.send('RegisterDashboard');
-      this.send('RequestStorageList');
+      this.authenticate();
     };
 
     this.socket.onmessage = (event) => {
@@ -88,8 +95,29 @@ export class Logos {
     };
   }
 
+  private authenticate() {
+    let token = localStorage.getItem(this.TOKEN_KEY);
+    if (!token) {
+      token = prompt('Access token') ?? '';
+      localStorage.setItem(this.TOKEN_KEY, token);
+    }
+    this.send({ Authenticate: { token } });
+  }
+
   private handleMessage(msg: any) {
-    if (msg.StorageList) {
+    if (msg.Authenticated) {
+      this.addActivity('system', `Authenticated as ${msg.Authenticated.user}`, 'System');
+      this.send('RegisterDashboard');
+      this.send('RequestStorageList');
+    }
+    else if (msg.Error) {
+      this.addActivity('error', msg.Error.message, 'Server');
+      if (msg.Error.message === 'Invalid token') {
+        localStorage.removeItem(this.TOKEN_KEY);
+        this.authenticate();
+      }
+    }
+    else if (msg.StorageList) {
       this.storages.set(msg.StorageList.storages);
     } 
     else if (msg.Welcome) {
@@ -97,11 +125,13 @@ export class Logos {
       this.files.set(activeFiles);
       this.addActivity('system', `Joined storage: ${msg.Welcome.storage_id.substring(0, 8)}...`, 'System');
     }
-    else if (msg.StartTransfer) {
-      const path = msg.StartTransfer.path;
-      if (this.downloadQueue.has(path)) {
-        this.nextBinaryMetadata = { path };
-        this.addActivity('system', `Downloading ${path}...`, 'System');
+    else if (msg.TransferChunk) {
+      const { path, offset } = msg.TransferChunk;
+      if (this.downloads.has(path)) {
+        this.nextBinaryMetadata = { path, offset };
+        if (offset === 0) {
+          this.addActivity('system', `Downloading ${path}...`, 'System');
+        }
       } else {
         this.nextBinaryMetadata = null;
       }
@@ -145,8 +175,9 @@ export class Logos {
 
   downloadFile(path: string) {
     if (!this.isConnected()) return;
-    this.downloadQueue.add(path);
-    this.send({ RequestFile: { path } });
+    const size = this.files().find(f => f.path === path)?.size ?? 0;
+    this.downloads.set(path, { size, received: 0, parts: [] });
+    this.send({ RequestFile: { path, offset: 0 } });
   }
 
   deleteFile(path: string) {
@@ -161,11 +192,21 @@ export class Logos {
 
   private handleBinary(blob: Blob) {
     if (this.nextBinaryMetadata) {
-      const path = this.nextBinaryMetadata.path;
-      this.triggerBrowserDownload(blob, path);
-      
-      this.downloadQueue.delete(path);
+      const { path, offset } = this.nextBinaryMetadata;
       this.nextBinaryMetadata = null;
+
+      const download = this.downloads.get(path);
+      if (!download || offset !== download.received) return;
+
+      download.parts.push(blob);
+      download.received += blob.size;
+
+      if (download.received >= download.size || blob.size === 0) {
+        this.triggerBrowserDownload(new Blob(download.parts), path);
+        this.downloads.delete(path);
+      } else {
+        this.send({ RequestFile: { path, offset: download.received } });
+      }
     }
   }
 
@@ -0,0 +1,107 @@
+use common::{ChunkInfo, ContentHasher, FileDigest};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+
+pub fn is_block_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+pub struct BlockStore {
+    root: PathBuf,
+}
+
+impl BlockStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn block_path(&self, hash: &str) -> std::io::Result<PathBuf> {
+        if !is_block_hash(hash) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid block hash: {}", hash)));
+        }
+        Ok(self.root.join(&hash[..2]).join(hash))
+    }
+
+    pub async fn claim(&self, hash: &str) -> bool {
+        let Ok(target) = self.block_path(hash) else { return false };
+        tokio::task::spawn_blocking(move || {
+            std::fs::File::options()
+                .append(true)
+                .open(target)
+                .and_then(|f| f.set_modified(SystemTime::now()))
+                .is_ok()
+        }).await.unwrap_or(false)
+    }
+
+    pub async fn get(&self, hash: &str) -> std::io::Result<Vec<u8>> {
+        fs::read(self.block_path(hash)?).await
+    }
+
+    pub async fn put(&self, hash: &str, data: &[u8]) -> std::io::Result<()> {
+        let target = self.block_path(hash)?;
+        if fs::metadata(&target).await.is_ok() {
+            return Ok(());
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let tmp = target.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+        fs::write(&tmp, data).await?;
+        fs::rename(&tmp, &target).await
+    }
+
+    pub async fn verify(&self, chunks: &[ChunkInfo], expected_hash: &str) -> std::io::Result<bool> {
+        let mut hasher = ContentHasher::new();
+        for chunk in chunks {
+            hasher.update(&self.get(&chunk.hash).await?);
+        }
+        Ok(hasher.finish() == expected_hash)
+    }
+
+    pub async fn import_file(&self, file_path: &Path) -> std::io::Result<FileDigest> {
+        let owned = file_path.to_path_buf();
+        let digest = tokio::task::spawn_blocking(move || common::digest_reader(std::fs::File::open(owned)?))
+            .await
+            .map_err(std::io::Error::other)??;
+
+        let mut file = fs::File::open(file_path).await?;
+        for chunk in &digest.chunks {
+            let mut buf = vec![0u8; chunk.size as usize];
+            file.read_exact(&mut buf).await?;
+            self.put(&chunk.hash, &buf).await?;
+        }
+        Ok(digest)
+    }
+
+    pub async fn collect_garbage(&self, referenced: &HashSet<String>, min_age: Duration) -> std::io::Result<usize> {
+        let mut removed = 0;
+        let now = SystemTime::now();
+        let mut prefixes = match fs::read_dir(&self.root).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+
+        while let Some(prefix) = prefixes.next_entry().await? {
+            if !prefix.file_type().await?.is_dir() { continue; }
+            let mut blocks = fs::read_dir(prefix.path()).await?;
+            while let Some(block) = blocks.next_entry().await? {
+                let name = block.file_name().to_string_lossy().to_string();
+                if referenced.contains(&name) { continue; }
+
+                let age = block.metadata().await?.modified()
+                    .ok()
+                    .and_then(|m| now.duration_since(m).ok())
+                    .unwrap_or_default();
+                if age < min_age { continue; }
+
+                fs::remove_file(block.path()).await?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
@@ -1,6 +1,7 @@
-use common::{FileMetadata, StorageInfo};
+use common::{EncryptionInfo, FileMetadata, Role, StorageInfo, calculate_hash};
+use sqlx::postgres::PgRow;
 use sqlx::{Pool, Postgres, Row};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
 pub async fn init_db(pool: &Pool<Postgres>) -> Result<(), sqlx::Error> {
@@ -30,38 +31,342 @@ pub async fn init_db(pool: &Pool<Postgres>) -> Result<(), sqlx::Error> {
         "#
     ).execute(pool).await?;
 
+    sqlx::query("ALTER TABLE storages ADD COLUMN IF NOT EXISTS encryption JSONB")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE storages ADD COLUMN IF NOT EXISTS ignore_patterns JSONB NOT NULL DEFAULT '[]'")
+        .execute(pool)
+        .await?;
+
     sqlx::query("ALTER TABLE files ADD COLUMN IF NOT EXISTS last_modified_by TEXT")
         .execute(pool)
         .await?;
-    
+
+    sqlx::query("ALTER TABLE files ADD COLUMN IF NOT EXISTS chunks JSONB NOT NULL DEFAULT '[]'")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS file_versions (
+            storage_id UUID NOT NULL REFERENCES storages(id),
+            path TEXT NOT NULL,
+            version BIGINT NOT NULL,
+            size BIGINT NOT NULL,
+            modified BIGINT NOT NULL,
+            hash TEXT NOT NULL,
+            is_deleted BOOLEAN NOT NULL DEFAULT FALSE,
+            last_modified_by TEXT,
+            chunks JSONB NOT NULL DEFAULT '[]',
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (storage_id, path, version)
+        );
+        "#
+    ).execute(pool).await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO file_versions (storage_id, path, version, size, modified, hash, is_deleted, last_modified_by, chunks)
+        SELECT storage_id, path, version, size, modified, hash, is_deleted, last_modified_by, chunks FROM files
+        ON CONFLICT DO NOTHING
+        "#
+    ).execute(pool).await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS users (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            name TEXT UNIQUE NOT NULL,
+            is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        );
+        "#
+    ).execute(pool).await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS tokens (
+            token_hash TEXT PRIMARY KEY,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            created_at TIMESTAMPTZ DEFAULT NOW(),
+            last_used_at TIMESTAMPTZ
+        );
+        "#
+    ).execute(pool).await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS storage_roles (
+            storage_id UUID NOT NULL REFERENCES storages(id),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            role TEXT NOT NULL CHECK (role IN ('read', 'write', 'admin')),
+            PRIMARY KEY (storage_id, user_id)
+        );
+        "#
+    ).execute(pool).await?;
+
+    sqlx::query(
+        r#"
+        DO $$ BEGIN
+            IF EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name = 'client_bases' AND column_name = 'client_name') THEN
+                DROP TABLE client_bases;
+            END IF;
+        END $$;
+        "#
+    ).execute(pool).await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS client_bases (
+            storage_id UUID NOT NULL REFERENCES storages(id),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            device_id TEXT NOT NULL,
+            path TEXT NOT NULL,
+            version BIGINT NOT NULL,
+            PRIMARY KEY (storage_id, user_id, device_id, path)
+        );
+        "#
+    ).execute(pool).await?;
+
     Ok(())
 }
 
+pub struct User {
+    pub id: Uuid,
+    pub name: String,
+    pub is_admin: bool,
+}
+
+#[derive(Default)]
+pub struct Device {
+    pub user_id: Uuid,
+    pub id: String,
+}
+
+fn generate_token() -> String {
+    (0..32).map(|_| format!("{:02x}", rand::random::<u8>())).collect()
+}
+
+pub async fn authenticate(pool: &Pool<Postgres>, token: &str) -> Result<Option<User>, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        UPDATE tokens SET last_used_at = NOW()
+        FROM users
+        WHERE tokens.token_hash = $1 AND users.id = tokens.user_id
+        RETURNING users.id, users.name, users.is_admin
+        "#
+    )
+        .bind(calculate_hash(token.as_bytes()))
+        .fetch_optional(pool)
+        .await?;
+
+    row.map(|r| Ok(User {
+        id: r.try_get("id")?,
+        name: r.try_get("name")?,
+        is_admin: r.try_get("is_admin")?,
+    })).transpose()
+}
+
+pub async fn count_users(pool: &Pool<Postgres>) -> Result<i64, sqlx::Error> {
+    sqlx::query("SELECT COUNT(*) AS n FROM users")
+        .fetch_one(pool)
+        .await?
+        .try_get("n")
+}
+
+pub async fn create_user(pool: &Pool<Postgres>, name: &str, is_admin: bool) -> Result<String, sqlx::Error> {
+    let token = generate_token();
+    let mut tx = pool.begin().await?;
+
+    let user_id: Uuid = sqlx::query("INSERT INTO users (name, is_admin) VALUES ($1, $2) RETURNING id")
+        .bind(name)
+        .bind(is_admin)
+        .fetch_one(&mut *tx)
+        .await?
+        .try_get("id")?;
+
+    sqlx::query("INSERT INTO tokens (token_hash, user_id) VALUES ($1, $2)")
+        .bind(calculate_hash(token.as_bytes()))
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(token)
+}
+
+pub async fn ensure_admin_token(pool: &Pool<Postgres>, token: &str) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let user_id: Uuid = sqlx::query(
+        r#"
+        INSERT INTO users (name, is_admin) VALUES ('admin', TRUE)
+        ON CONFLICT (name) DO UPDATE SET is_admin = TRUE
+        RETURNING id
+        "#
+    )
+        .fetch_one(&mut *tx)
+        .await?
+        .try_get("id")?;
+
+    sqlx::query("INSERT INTO tokens (token_hash, user_id) VALUES ($1, $2) ON CONFLICT (token_hash) DO UPDATE SET user_id = EXCLUDED.user_id")
+        .bind(calculate_hash(token.as_bytes()))
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn storage_role(pool: &Pool<Postgres>, storage_id: &str, user: &User) -> Result<Option<Role>, sqlx::Error> {
+    if user.is_admin {
+        return Ok(Some(Role::Admin));
+    }
+    let Ok(uuid) = Uuid::parse_str(storage_id) else { return Ok(None) };
+
+    let row = sqlx::query("SELECT role FROM storage_roles WHERE storage_id = $1 AND user_id = $2")
+        .bind(uuid)
+        .bind(user.id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(match row {
+        Some(r) => Role::parse(&r.try_get::<String, _>("role")?),
+        None => None,
+    })
+}
+
+pub async fn set_role(pool: &Pool<Postgres>, storage_id: &str, user_name: &str, role: Option<Role>) -> Result<bool, sqlx::Error> {
+    let uuid = Uuid::parse_str(storage_id)
+        .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+
+    let Some(user_id) = sqlx::query("SELECT id FROM users WHERE name = $1")
+        .bind(user_name)
+        .fetch_optional(pool)
+        .await?
+        .map(|r| r.try_get::<Uuid, _>("id"))
+        .transpose()? else { return Ok(false) };
+
+    match role {
+        Some(role) => {
+            sqlx::query(
+                r#"
+                INSERT INTO storage_roles (storage_id, user_id, role) VALUES ($1, $2, $3)
+                ON CONFLICT (storage_id, user_id) DO UPDATE SET role = EXCLUDED.role
+                "#
+            )
+                .bind(uuid)
+                .bind(user_id)
+                .bind(role.as_str())
+                .execute(pool)
+                .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM storage_roles WHERE storage_id = $1 AND user_id = $2")
+                .bind(uuid)
+                .bind(user_id)
+                .execute(pool)
+                .await?;
+        }
+    }
+    Ok(true)
+}
+
 pub async fn list_storages(pool: &Pool<Postgres>) -> Result<Vec<StorageInfo>, sqlx::Error> {
-    let rows = sqlx::query("SELECT id, name FROM storages ORDER BY name ASC")
+    let rows = sqlx::query("SELECT id, name, encryption::TEXT AS encryption FROM storages ORDER BY name ASC")
         .fetch_all(pool)
         .await?;
 
-    let mut storages = Vec::new();
-    for r in rows {
-        storages.push(StorageInfo {
-            id: r.try_get::<Uuid, _>("id")?.to_string(),
-            name: r.try_get("name")?,
-        });
+    rows.iter().map(row_to_storage).collect()
+}
+
+pub async fn list_storages_for(pool: &Pool<Postgres>, user: &User) -> Result<Vec<StorageInfo>, sqlx::Error> {
+    if user.is_admin {
+        return list_storages(pool).await;
     }
-    Ok(storages)
+
+    let rows = sqlx::query(
+        r#"
+        SELECT s.id, s.name, s.encryption::TEXT AS encryption FROM storages s
+        JOIN storage_roles r ON r.storage_id = s.id
+        WHERE r.user_id = $1
+        ORDER BY s.name ASC
+        "#
+    )
+        .bind(user.id)
+        .fetch_all(pool)
+        .await?;
+
+    rows.iter().map(row_to_storage).collect()
+}
+
+pub async fn load_storage(pool: &Pool<Postgres>, storage_id: &str) -> Result<Option<StorageInfo>, sqlx::Error> {
+    let Ok(uuid) = Uuid::parse_str(storage_id) else { return Ok(None) };
+
+    sqlx::query("SELECT id, name, encryption::TEXT AS encryption FROM storages WHERE id = $1")
+        .bind(uuid)
+        .fetch_optional(pool)
+        .await?
+        .as_ref()
+        .map(row_to_storage)
+        .transpose()
 }
 
-pub async fn create_storage(pool: &Pool<Postgres>, name: &str) -> Result<StorageInfo, sqlx::Error> {
-    let row = sqlx::query("INSERT INTO storages (name) VALUES ($1) RETURNING id, name")
+pub async fn load_ignore_patterns(pool: &Pool<Postgres>, storage_id: &str) -> Result<Vec<String>, sqlx::Error> {
+    let uuid = Uuid::parse_str(storage_id)
+        .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+
+    let row = sqlx::query("SELECT ignore_patterns::TEXT AS ignore_patterns FROM storages WHERE id = $1")
+        .bind(uuid)
+        .fetch_optional(pool)
+        .await?;
+
+    match row {
+        Some(row) => serde_json::from_str(row.try_get("ignore_patterns")?)
+            .map_err(|e| sqlx::Error::Decode(Box::new(e))),
+        None => Ok(Vec::new()),
+    }
+}
+
+pub async fn set_ignore_patterns(pool: &Pool<Postgres>, storage_id: &str, patterns: &[String]) -> Result<(), sqlx::Error> {
+    let uuid = Uuid::parse_str(storage_id)
+        .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+    let patterns = serde_json::to_string(patterns).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+
+    sqlx::query("UPDATE storages SET ignore_patterns = $2::JSONB WHERE id = $1")
+        .bind(uuid)
+        .bind(patterns)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn create_storage(pool: &Pool<Postgres>, name: &str, encryption: Option<&EncryptionInfo>, owner: &User) -> Result<StorageInfo, sqlx::Error> {
+    let encryption = encryption
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+
+    let mut tx = pool.begin().await?;
+
+    let row = sqlx::query("INSERT INTO storages (name, encryption) VALUES ($1, $2::JSONB) RETURNING id, name, encryption::TEXT AS encryption")
         .bind(name)
-        .fetch_one(pool)
+        .bind(encryption)
+        .fetch_one(&mut *tx)
         .await?;
+    let storage = row_to_storage(&row)?;
+    let id: Uuid = row.try_get("id")?;
 
-    Ok(StorageInfo {
-        id: row.try_get::<Uuid, _>("id")?.to_string(),
-        name: row.try_get("name")?,
-    })
+    sqlx::query("INSERT INTO storage_roles (storage_id, user_id, role) VALUES ($1, $2, 'admin')")
+        .bind(id)
+        .bind(owner.id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(storage)
 }
 
 pub async fn delete_storage(pool: &Pool<Postgres>, storage_id: &str) -> Result<(), sqlx::Error> {
@@ -70,10 +375,25 @@ pub async fn delete_storage(pool: &Pool<Postgres>, storage_id: &str) -> Result<(
 
     let mut tx = pool.begin().await?;
     
+    sqlx::query("DELETE FROM file_versions WHERE storage_id = $1")
+        .bind(uuid)
+        .execute(&mut *tx)
+        .await?;
+
     sqlx::query("DELETE FROM files WHERE storage_id = $1")
         .bind(uuid)
         .execute(&mut *tx)
         .await?;
+
+    sqlx::query("DELETE FROM storage_roles WHERE storage_id = $1")
+        .bind(uuid)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM client_bases WHERE storage_id = $1")
+        .bind(uuid)
+        .execute(&mut *tx)
+        .await?;
         
     sqlx::query("DELETE FROM storages WHERE id = $1")
         .bind(uuid)
@@ -88,42 +408,64 @@ pub async fn load_storage_files(pool: &Pool<Postgres>, storage_id: &str) -> Resu
     let uuid = Uuid::parse_str(storage_id)
         .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
     
-    let rows = sqlx::query("SELECT path, size, modified, version, hash, is_deleted, last_modified_by FROM files WHERE storage_id = $1")
+    let rows = sqlx::query("SELECT path, size, modified, version, hash, is_deleted, last_modified_by, chunks::TEXT AS chunks FROM files WHERE storage_id = $1")
         .bind(uuid)
         .fetch_all(pool)
         .await?;
 
     let mut map = HashMap::new();
     for row in rows {
-        let meta = FileMetadata {
-            path: row.try_get("path")?,
-            size: row.try_get::<i64, _>("size")? as u64,
-            modified: row.try_get::<i64, _>("modified")? as u64,
-            version: row.try_get::<i64, _>("version")? as u64,
-            hash: row.try_get("hash")?,
-            is_deleted: row.try_get("is_deleted")?,
-            last_modified_by: row.try_get("last_modified_by")?,
-        };
+        let meta = row_to_meta(&row)?;
         map.insert(meta.path.clone(), meta);
     }
     Ok(map)
 }
 
+fn row_to_storage(row: &PgRow) -> Result<StorageInfo, sqlx::Error> {
+    let encryption: Option<String> = row.try_get("encryption")?;
+    Ok(StorageInfo {
+        id: row.try_get::<Uuid, _>("id")?.to_string(),
+        name: row.try_get("name")?,
+        encryption: encryption
+            .map(|raw| serde_json::from_str(&raw))
+            .transpose()
+            .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+    })
+}
+
+fn row_to_meta(row: &PgRow) -> Result<FileMetadata, sqlx::Error> {
+    Ok(FileMetadata {
+        path: row.try_get("path")?,
+        size: row.try_get::<i64, _>("size")? as u64,
+        modified: row.try_get::<i64, _>("modified")? as u64,
+        version: row.try_get::<i64, _>("version")? as u64,
+        hash: row.try_get("hash")?,
+        is_deleted: row.try_get("is_deleted")?,
+        last_modified_by: row.try_get("last_modified_by")?,
+        chunks: serde_json::from_str(row.try_get("chunks")?)
+            .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+    })
+}
+
 pub async fn save_file(pool: &Pool<Postgres>, storage_id: &str, meta: &FileMetadata) -> Result<(), sqlx::Error> {
     let uuid = Uuid::parse_str(storage_id)
         .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+    let chunks = serde_json::to_string(&meta.chunks).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+
+    let mut tx = pool.begin().await?;
 
     sqlx::query(
         r#"
-        INSERT INTO files (storage_id, path, size, modified, version, hash, is_deleted, last_modified_by)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        INSERT INTO files (storage_id, path, size, modified, version, hash, is_deleted, last_modified_by, chunks)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9::JSONB)
         ON CONFLICT (storage_id, path) DO UPDATE
         SET size = EXCLUDED.size,
             modified = EXCLUDED.modified,
             version = EXCLUDED.version,
             hash = EXCLUDED.hash,
             is_deleted = EXCLUDED.is_deleted,
-            last_modified_by = EXCLUDED.last_modified_by
+            last_modified_by = EXCLUDED.last_modified_by,
+            chunks = EXCLUDED.chunks
         "#
     )
     .bind(uuid)
@@ -134,8 +476,151 @@ pub async fn save_file(pool: &Pool<Postgres>, storage_id: &str, meta: &FileMetad
     .bind(&meta.hash)
     .bind(meta.is_deleted)
     .bind(&meta.last_modified_by)
+    .bind(&chunks)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO file_versions (storage_id, path, version, size, modified, hash, is_deleted, last_modified_by, chunks)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9::JSONB)
+        ON CONFLICT (storage_id, path, version) DO UPDATE
+        SET size = EXCLUDED.size,
+            modified = EXCLUDED.modified,
+            hash = EXCLUDED.hash,
+            is_deleted = EXCLUDED.is_deleted,
+            last_modified_by = EXCLUDED.last_modified_by,
+            chunks = EXCLUDED.chunks
+        "#
+    )
+    .bind(uuid)
+    .bind(&meta.path)
+    .bind(meta.version as i64)
+    .bind(meta.size as i64)
+    .bind(meta.modified as i64)
+    .bind(&meta.hash)
+    .bind(meta.is_deleted)
+    .bind(&meta.last_modified_by)
+    .bind(&chunks)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn list_versions(pool: &Pool<Postgres>, storage_id: &str, path: &str) -> Result<Vec<FileMetadata>, sqlx::Error> {
+    let uuid = Uuid::parse_str(storage_id)
+        .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+
+    let rows = sqlx::query("SELECT path, size, modified, version, hash, is_deleted, last_modified_by, chunks::TEXT AS chunks FROM file_versions WHERE storage_id = $1 AND path = $2 ORDER BY version DESC")
+        .bind(uuid)
+        .bind(path)
+        .fetch_all(pool)
+        .await?;
+
+    rows.iter().map(row_to_meta).collect()
+}
+
+pub async fn load_version(pool: &Pool<Postgres>, storage_id: &str, path: &str, version: u64) -> Result<Option<FileMetadata>, sqlx::Error> {
+    let uuid = Uuid::parse_str(storage_id)
+        .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+
+    let row = sqlx::query("SELECT path, size, modified, version, hash, is_deleted, last_modified_by, chunks::TEXT AS chunks FROM file_versions WHERE storage_id = $1 AND path = $2 AND version = $3")
+        .bind(uuid)
+        .bind(path)
+        .bind(version as i64)
+        .fetch_optional(pool)
+        .await?;
+
+    row.as_ref().map(row_to_meta).transpose()
+}
+
+pub async fn prune_versions(pool: &Pool<Postgres>, storage_id: &str, path: &str, keep_last: u64, keep_days: Option<u64>) -> Result<u64, sqlx::Error> {
+    let uuid = Uuid::parse_str(storage_id)
+        .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+
+    let result = sqlx::query(
+        r#"
+        DELETE FROM file_versions
+        WHERE storage_id = $1 AND path = $2
+          AND version NOT IN (
+              SELECT version FROM file_versions
+              WHERE storage_id = $1 AND path = $2
+              ORDER BY version DESC
+              LIMIT $3
+          )
+          AND ($4::BIGINT IS NULL OR created_at < NOW() - make_interval(days => $4::INT))
+          AND version NOT IN (
+              SELECT version FROM client_bases
+              WHERE storage_id = $1 AND path = $2
+          )
+        "#
+    )
+    .bind(uuid)
+    .bind(path)
+    .bind(keep_last as i64)
+    .bind(keep_days.map(|d| d as i64))
     .execute(pool)
     .await?;
 
+    Ok(result.rows_affected())
+}
+
+pub async fn load_base(pool: &Pool<Postgres>, storage_id: &str, device: &Device, path: &str) -> Result<Option<u64>, sqlx::Error> {
+    let uuid = Uuid::parse_str(storage_id)
+        .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+
+    let row = sqlx::query("SELECT version FROM client_bases WHERE storage_id = $1 AND user_id = $2 AND device_id = $3 AND path = $4")
+        .bind(uuid)
+        .bind(device.user_id)
+        .bind(&device.id)
+        .bind(path)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(match row {
+        Some(r) => Some(r.try_get::<i64, _>("version")? as u64),
+        None => None,
+    })
+}
+
+pub async fn save_base(pool: &Pool<Postgres>, storage_id: &str, device: &Device, path: &str, version: u64) -> Result<(), sqlx::Error> {
+    let uuid = Uuid::parse_str(storage_id)
+        .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO client_bases (storage_id, user_id, device_id, path, version) VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (storage_id, user_id, device_id, path) DO UPDATE SET version = EXCLUDED.version
+        "#
+    )
+        .bind(uuid)
+        .bind(device.user_id)
+        .bind(&device.id)
+        .bind(path)
+        .bind(version as i64)
+        .execute(pool)
+        .await?;
     Ok(())
+}
+
+pub async fn referenced_chunks(pool: &Pool<Postgres>) -> Result<HashSet<String>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT c->>'hash' AS hash FROM files, jsonb_array_elements(chunks) c
+        UNION
+        SELECT c->>'hash' AS hash FROM file_versions, jsonb_array_elements(chunks) c
+        "#
+    )
+        .fetch_all(pool)
+        .await?;
+
+    let mut hashes = HashSet::new();
+    for row in rows {
+        if let Some(hash) = row.try_get::<Option<String>, _>("hash")? {
+            hashes.insert(hash);
+        }
+    }
+    Ok(hashes)
 }
\ No newline at end of file
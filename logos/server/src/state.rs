@@ -1,16 +1,35 @@
-use common::{FileMetadata, DashboardMessage, Message, ClientInfo};
+use common::{FileMetadata, DashboardMessage, Message, ClientInfo, Subscription};
 use dashmap::DashMap;
 use sqlx::{Pool, Postgres};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
 use tokio::sync::mpsc;
+use crate::blocks::BlockStore;
+use crate::config::ServerConfig;
 use crate::db;
 
 pub type ClientSender = mpsc::UnboundedSender<axum::extract::ws::Message>;
 pub type DashboardSender = mpsc::UnboundedSender<axum::extract::ws::Message>;
 
+pub struct RoomClient {
+    pub tx: ClientSender,
+    pub multiplexed: bool,
+    pub subscription: Subscription,
+}
+
+fn message_path(msg: &Message) -> Option<&str> {
+    match msg {
+        Message::FileUpdate { meta } => Some(&meta.path),
+        Message::StartTransfer { path, .. } | Message::DeleteFile { path } => Some(path),
+        _ => None,
+    }
+}
+
 pub struct StorageRoom {
     pub files: DashMap<String, FileMetadata>,
-    pub clients: DashMap<String, ClientSender>,
+    pub clients: DashMap<String, RoomClient>,
     pub client_names: DashMap<String, String>,
 }
 
@@ -24,21 +43,58 @@ impl StorageRoom {
     }
 }
 
+pub struct VersionRetention {
+    pub keep_last: u64,
+    pub keep_days: Option<u64>,
+}
+
+impl VersionRetention {
+    pub fn from_env() -> Self {
+        Self {
+            keep_last: std::env::var("LOGOS_KEEP_VERSIONS").ok().and_then(|v| v.parse().ok()).unwrap_or(10),
+            keep_days: std::env::var("LOGOS_KEEP_VERSIONS_DAYS").ok().and_then(|v| v.parse().ok()),
+        }
+    }
+}
+
+async fn bootstrap_admin(pool: &Pool<Postgres>) {
+    if let Ok(token) = std::env::var("LOGOS_ADMIN_TOKEN") {
+        db::ensure_admin_token(pool, &token).await.expect("Failed to register admin token");
+        return;
+    }
+
+    if db::count_users(pool).await.unwrap_or(0) == 0 {
+        match db::create_user(pool, "admin", true).await {
+            Ok(token) => tracing::warn!("No users configured. Created 'admin' with token: {}", token),
+            Err(e) => tracing::error!("Failed to create admin user: {}", e),
+        }
+    }
+}
+
 pub struct AppState {
     pub rooms: DashMap<String, Arc<StorageRoom>>, 
     pub dashboards: DashMap<usize, DashboardSender>,
     pub db: Pool<Postgres>,
+    pub blocks: BlockStore,
+    pub retention: VersionRetention,
+    pub uploads_root: PathBuf,
+    pub max_message_size: usize,
 }
 
 impl AppState {
-    pub async fn new(db_url: &str) -> Self {
-        let pool = Pool::<Postgres>::connect(db_url).await.expect("Failed to connect to DB");
+    pub async fn new(config: &ServerConfig) -> Self {
+        let pool = Pool::<Postgres>::connect(&config.database_url).await.expect("Failed to connect to DB");
         db::init_db(&pool).await.expect("Failed to init DB schema");
+        bootstrap_admin(&pool).await;
         
         Self {
             rooms: DashMap::new(),
             dashboards: DashMap::new(),
             db: pool,
+            blocks: BlockStore::new(config.uploads_root.join("blocks")),
+            retention: VersionRetention::from_env(),
+            uploads_root: config.uploads_root.clone(),
+            max_message_size: config.max_message_size,
         }
     }
 
@@ -49,7 +105,10 @@ impl AppState {
 
         let files = db::load_storage_files(&self.db, storage_id).await.unwrap_or_default();
         let room = Arc::new(StorageRoom::new());
-        for (k, v) in files {
+        for (k, mut v) in files {
+            if v.chunks.is_empty() && v.size > 0 && !v.is_deleted {
+                self.import_legacy_file(storage_id, &mut v).await;
+            }
             room.files.insert(k, v);
         }
 
@@ -57,6 +116,37 @@ impl AppState {
         room
     }
 
+    async fn import_legacy_file(&self, storage_id: &str, meta: &mut FileMetadata) {
+        let legacy = self.uploads_root.join(storage_id).join(&meta.path);
+        match self.blocks.import_file(&legacy).await {
+            Ok(digest) => {
+                meta.chunks = digest.chunks;
+                if let Err(e) = db::save_file(&self.db, storage_id, meta).await {
+                    tracing::error!("Database error: {}", e);
+                    return;
+                }
+                let _ = fs::remove_file(&legacy).await;
+            }
+            Err(e) => tracing::warn!("Could not import {} into block store: {}", meta.path, e),
+        }
+    }
+
+    pub async fn collect_garbage(&self) {
+        let referenced = match db::referenced_chunks(&self.db).await {
+            Ok(referenced) => referenced,
+            Err(e) => {
+                tracing::error!("Database error: {}", e);
+                return;
+            }
+        };
+
+        match self.blocks.collect_garbage(&referenced, Duration::from_secs(24 * 60 * 60)).await {
+            Ok(0) => {}
+            Ok(removed) => self.emit_log("info", &format!("Removed {} unreferenced blocks", removed)),
+            Err(e) => self.emit_log("error", &format!("Block garbage collection failed: {}", e)),
+        }
+    }
+
     pub async fn process_update(&self, storage_id: &str, incoming: FileMetadata) -> Option<FileMetadata> {
         let room = self.get_or_load_room(storage_id).await;
 
@@ -71,6 +161,10 @@ impl AppState {
             return None;
         }
 
+        if let Err(e) = db::prune_versions(&self.db, storage_id, &new_state.path, self.retention.keep_last, self.retention.keep_days).await {
+            tracing::error!("Failed to prune versions of {}: {}", new_state.path, e);
+        }
+
         room.files.insert(new_state.path.clone(), new_state.clone());
         self.emit_log("info", &format!("File updated in {}: {}", storage_id, new_state.path));
         
@@ -79,11 +173,38 @@ impl AppState {
         Some(new_state)
     }
 
-    pub async fn broadcast(&self, storage_id: &str, sender_id: &str, msg: axum::extract::ws::Message) {
+    pub async fn restore_version(&self, storage_id: &str, path: &str, version: u64, client_name: &str) -> Result<FileMetadata, String> {
+        let past = db::load_version(&self.db, storage_id, path, version).await
+            .map_err(|e| format!("Failed to load version: {}", e))?
+            .ok_or_else(|| format!("Version {} of {} not found", version, path))?;
+
+        let room = self.get_or_load_room(storage_id).await;
+        let current_version = room.files.get(path).map(|m| m.version).unwrap_or(0);
+
+        let restored = FileMetadata {
+            version: current_version + 1,
+            modified: chrono::Utc::now().timestamp() as u64,
+            last_modified_by: Some(client_name.to_string()),
+            ..past
+        };
+
+        let updated = self.process_update(storage_id, restored).await
+            .ok_or_else(|| format!("Could not restore {} to version {}", path, version))?;
+        self.emit_log("info", &format!("{} restored {} to version {}", client_name, path, version));
+        Ok(updated)
+    }
+
+    pub async fn broadcast(&self, storage_id: &str, sender_id: &str, msg: &Message) {
         if let Some(room) = self.rooms.get(storage_id) {
+            let plain = serde_json::to_string(msg);
+            let scoped = serde_json::to_string(&Message::Scoped { storage_id: storage_id.to_string(), message: Box::new(msg.clone()) });
+            let path = message_path(msg);
             for client in room.clients.iter() {
-                if client.key() != sender_id {
-                    let _ = client.value().send(msg.clone());
+                if client.key() == sender_id { continue; }
+                if let Some(path) = path && !client.subscription.covers(path) { continue; }
+                let json = if client.multiplexed { &scoped } else { &plain };
+                if let Ok(json) = json {
+                    let _ = client.tx.send(axum::extract::ws::Message::Text(json.clone()));
                 }
             }
         }
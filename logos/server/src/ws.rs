@@ -1,31 +1,189 @@
-use crate::state::SharedState;
+use crate::blocks;
+use crate::state::{RoomClient, SharedState};
 use crate::db;
 use axum::{
     extract::{ws::{Message as WsMessage, WebSocket, WebSocketUpgrade}, State},
     response::IntoResponse,
 };
-use common::{Message, FileMetadata};
+use common::{ChunkInfo, Message, FileMetadata, Role, CHUNK_WINDOW, MAX_CHUNK_SIZE};
 use futures::{sink::SinkExt, stream::StreamExt};
 use tokio::sync::mpsc;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet, VecDeque};
 use tokio::fs;
 
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<SharedState>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_socket(socket, state))
+    ws.max_message_size(state.max_message_size)
+        .max_frame_size(state.max_message_size)
+        .on_upgrade(|socket| handle_socket(socket, state))
 }
 
 enum SessionState {
     Lobby,
-    Synced { storage_id: String },
+    Synced { storages: HashMap<String, Role> },
     Dashboard,
 }
 
+impl SessionState {
+    fn joined(&self, scope: Option<&str>) -> Option<(String, Role)> {
+        let SessionState::Synced { storages } = self else { return None };
+        let (storage_id, role) = match scope {
+            Some(id) => storages.get_key_value(id)?,
+            None if storages.len() == 1 => storages.iter().next()?,
+            None => return None,
+        };
+        Some((storage_id.clone(), *role))
+    }
+}
+
 enum TransferState {
     Idle,
-    ExpectingBinary { path: String, meta: FileMetadata },
+    ExpectingChunk { scope: Option<String>, path: String, hash: String },
+}
+
+struct Outbox<'a> {
+    tx: &'a mpsc::UnboundedSender<WsMessage>,
+    scope: Option<&'a str>,
+}
+
+impl Outbox<'_> {
+    fn send(&self, msg: Message) {
+        match self.scope {
+            Some(storage_id) => send_json(self.tx, &Message::Scoped { storage_id: storage_id.to_string(), message: Box::new(msg) }),
+            None => send_json(self.tx, &msg),
+        }
+    }
+
+    fn send_binary(&self, data: Vec<u8>) {
+        self.tx.send(WsMessage::Binary(data)).ok();
+    }
+}
+
+struct PendingUpload {
+    meta: FileMetadata,
+    missing: VecDeque<ChunkInfo>,
+    requested: Vec<ChunkInfo>,
+}
+
+fn send_json(tx: &mpsc::UnboundedSender<WsMessage>, msg: &Message) {
+    if let Ok(json) = serde_json::to_string(msg) {
+        tx.send(WsMessage::Text(json)).ok();
+    }
+}
+
+fn permitted(out: &Outbox, role: Option<Role>, needed: Role, action: &str) -> bool {
+    if role.is_some_and(|r| r >= needed) {
+        return true;
+    }
+    out.send(Message::Error { message: format!("Permission denied: {} requires {} access", action, needed.as_str()) });
+    false
+}
+
+fn chunks_are_contiguous(chunks: &[ChunkInfo], size: u64) -> bool {
+    let mut offset = 0;
+    for chunk in chunks {
+        if chunk.offset != offset || chunk.size as usize > MAX_CHUNK_SIZE || !blocks::is_block_hash(&chunk.hash) { return false; }
+        offset += chunk.size;
+    }
+    offset == size
+}
+
+async fn send_chunk(state: &SharedState, out: &Outbox<'_>, path: &str, chunk: &ChunkInfo, skip: u64) {
+    match state.blocks.get(&chunk.hash).await {
+        Ok(data) => {
+            let data = data.get(skip as usize..).unwrap_or_default().to_vec();
+            out.send(Message::TransferChunk {
+                path: path.to_string(),
+                offset: chunk.offset + skip,
+                hash: common::calculate_hash(&data),
+            });
+            out.send_binary(data);
+        }
+        Err(e) => {
+            state.emit_log("error", &format!("Missing block {} for {}: {}", chunk.hash, path, e));
+        }
+    }
+}
+
+async fn send_conflict(
+    state: &SharedState,
+    out: &Outbox<'_>,
+    storage_id: &str,
+    path: String,
+    server_version: u64,
+    base_version: Option<u64>,
+) {
+    let base = match base_version {
+        Some(version) => db::load_version(&state.db, storage_id, &path, version).await.ok().flatten(),
+        None => None,
+    };
+    out.send(Message::ConflictDetected { path, server_version, base });
+}
+
+async fn advance_upload(
+    state: &SharedState,
+    storage_id: &str,
+    client_id: &str,
+    device: &db::Device,
+    out: &Outbox<'_>,
+    uploads: &mut HashMap<String, PendingUpload>,
+    mut upload: PendingUpload,
+) {
+    if !upload.requested.is_empty() {
+        uploads.insert(upload.meta.path.clone(), upload);
+        return;
+    }
+
+    if upload.missing.is_empty() {
+        finish_upload(state, storage_id, client_id, device, out, upload).await;
+        return;
+    }
+
+    let window = upload.missing.len().min(CHUNK_WINDOW);
+    upload.requested = upload.missing.drain(..window).collect();
+    out.send(Message::RequestChunks { path: upload.meta.path.clone(), chunks: upload.requested.clone() });
+    uploads.insert(upload.meta.path.clone(), upload);
+}
+
+async fn finish_upload(
+    state: &SharedState,
+    storage_id: &str,
+    client_id: &str,
+    device: &db::Device,
+    out: &Outbox<'_>,
+    upload: PendingUpload,
+) {
+    let path = upload.meta.path.clone();
+    match state.blocks.verify(&upload.meta.chunks, &upload.meta.hash).await {
+        Ok(true) => {}
+        Ok(false) => {
+            state.emit_log("error", &format!("Upload of {} failed verification", path));
+            out.send(Message::Error { message: format!("Upload of {} failed verification", path) });
+            return;
+        }
+        Err(e) => {
+            state.emit_log("error", &format!("Failed to read blocks for {}: {}", path, e));
+            return;
+        }
+    }
+
+    if let Some(updated_meta) = state.process_update(storage_id, upload.meta).await {
+        if let Err(e) = db::save_base(&state.db, storage_id, device, &updated_meta.path, updated_meta.version).await {
+            state.emit_log("error", &format!("Failed to record base of {}: {}", updated_meta.path, e));
+        }
+        let update_msg = Message::FileUpdate { meta: updated_meta };
+        state.broadcast(storage_id, client_id, &update_msg).await;
+        out.send(update_msg);
+    } else {
+        let room = state.get_or_load_room(storage_id).await;
+        let server_version = room.files.get(&path).map(|current| current.version);
+        if let Some(server_version) = server_version {
+            let base_version = db::load_base(&state.db, storage_id, device, &path).await.ok().flatten();
+            send_conflict(state, out, storage_id, path, server_version, base_version).await;
+        }
+    }
 }
 
 async fn handle_socket(socket: WebSocket, state: SharedState) {
@@ -40,16 +198,91 @@ async fn handle_socket(socket: WebSocket, state: SharedState) {
 
     let mut session = SessionState::Lobby;
     let mut transfer_state = TransferState::Idle;
+    let mut uploads: HashMap<String, HashMap<String, PendingUpload>> = HashMap::new();
     let client_id = uuid::Uuid::new_v4().to_string();
     let dashboard_id = rand::random::<usize>();
     let mut client_name = "Unknown".to_string();
+    let mut device = db::Device::default();
+    let mut user: Option<db::User> = None;
 
     while let Some(Ok(msg)) = receiver.next().await {
         match msg {
             WsMessage::Text(text) => {
                 if let Ok(parsed) = serde_json::from_str::<Message>(&text) {
+                    let Some(current_user) = &user else {
+                        if let Message::Authenticate { token } = parsed {
+                            match db::authenticate(&state.db, &token).await {
+                                Ok(Some(found)) => {
+                                    send_json(&tx, &Message::Authenticated { user: found.name.clone() });
+                                    device.user_id = found.id;
+                                    user = Some(found);
+                                }
+                                Ok(None) => {
+                                    state.emit_log("warn", "Rejected connection with invalid token");
+                                    send_json(&tx, &Message::Error { message: "Invalid token".to_string() });
+                                }
+                                Err(e) => {
+                                    state.emit_log("error", &format!("Token lookup failed: {}", e));
+                                    send_json(&tx, &Message::Error { message: "Authentication unavailable".to_string() });
+                                }
+                            }
+                        } else {
+                            send_json(&tx, &Message::Error { message: "Authentication required".to_string() });
+                        }
+                        continue;
+                    };
+
+                    let (scope, parsed) = match parsed {
+                        Message::Scoped { storage_id, message } => (Some(storage_id), *message),
+                        other => (None, other),
+                    };
+                    let target = session.joined(scope.as_deref());
+                    let out = Outbox { tx: &tx, scope: scope.as_deref() };
+
                     match parsed {
+                        Message::CreateUser { name, admin } => {
+                            if !current_user.is_admin {
+                                send_json(&tx, &Message::Error { message: "Permission denied: only server admins can create users".to_string() });
+                                continue;
+                            }
+                            match db::create_user(&state.db, &name, admin).await {
+                                Ok(token) => {
+                                    state.emit_log("info", &format!("User created: {}", name));
+                                    send_json(&tx, &Message::UserCreated { name, token });
+                                }
+                                Err(e) => send_json(&tx, &Message::Error { message: format!("Create user failed: {}", e) }),
+                            }
+                        },
+                        Message::SetRole { storage_id, user: target, role } => {
+                            let own_role = db::storage_role(&state.db, &storage_id, current_user).await.ok().flatten();
+                            if !permitted(&out, own_role, Role::Admin, "SetRole") { continue; }
+                            match db::set_role(&state.db, &storage_id, &target, role).await {
+                                Ok(true) => {
+                                    state.emit_log("info", &format!("Role of {} on {} set to {}", target, storage_id, role.map(|r| r.as_str()).unwrap_or("none")));
+                                    send_json(&tx, &Message::RoleUpdated { storage_id, user: target, role });
+                                }
+                                Ok(false) => send_json(&tx, &Message::Error { message: format!("Unknown user: {}", target) }),
+                                Err(e) => send_json(&tx, &Message::Error { message: format!("SetRole failed: {}", e) }),
+                            }
+                        },
+                        Message::SetIgnorePatterns { storage_id, patterns } => {
+                            let own_role = db::storage_role(&state.db, &storage_id, current_user).await.ok().flatten();
+                            if !permitted(&out, own_role, Role::Admin, "SetIgnorePatterns") { continue; }
+                            match db::set_ignore_patterns(&state.db, &storage_id, &patterns).await {
+                                Ok(()) => {
+                                    state.emit_log("info", &format!("Ignore patterns of {} set to {:?}", storage_id, patterns));
+                                    let update = Message::IgnorePatternsUpdated { storage_id: storage_id.clone(), patterns };
+                                    out.send(update.clone());
+                                    state.broadcast(&storage_id, &client_id, &update).await;
+                                }
+                                Err(e) => out.send(Message::Error { message: format!("SetIgnorePatterns failed: {}", e) }),
+                            }
+                        },
                         Message::RegisterDashboard => {
+                            if !current_user.is_admin {
+                                send_json(&tx, &Message::Error { message: "Permission denied: dashboard requires a server admin".to_string() });
+                                continue;
+                            }
                             state.dashboards.insert(dashboard_id, tx.clone());
                             session = SessionState::Dashboard;
                             client_name = "Dashboard".to_string();
@@ -57,20 +290,20 @@ async fn handle_socket(socket: WebSocket, state: SharedState) {
                             state.emit_stats();
                         },
                         Message::RequestStorageList => {
-                            if let Ok(list) = db::list_storages(&state.db).await {
+                            if let Ok(list) = db::list_storages_for(&state.db, current_user).await {
                                 let resp = Message::StorageList { storages: list };
                                 if let Ok(json) = serde_json::to_string(&resp) {
                                     tx.send(WsMessage::Text(json)).ok();
                                 }
                             }
                         },
-                        Message::CreateStorage { name } => {
-                            match db::create_storage(&state.db, &name).await {
+                        Message::CreateStorage { name, encryption } => {
+                            match db::create_storage(&state.db, &name, encryption.as_ref(), current_user).await {
                                 Ok(_) => {
                                     state.emit_storage_list().await;
                                     
                                     if !matches!(session, SessionState::Dashboard) 
-                                        && let Ok(list) = db::list_storages(&state.db).await {
+                                        && let Ok(list) = db::list_storages_for(&state.db, current_user).await {
                                             let resp = Message::StorageList { storages: list };
                                             if let Ok(json) = serde_json::to_string(&resp) {
                                                 tx.send(WsMessage::Text(json)).ok();
@@ -86,19 +319,22 @@ async fn handle_socket(socket: WebSocket, state: SharedState) {
                             }
                         },
                         Message::DeleteStorage { storage_id } => {
+                            let role = db::storage_role(&state.db, &storage_id, current_user).await.ok().flatten();
+                            if !permitted(&out, role, Role::Admin, "DeleteStorage") { continue; }
                             match db::delete_storage(&state.db, &storage_id).await {
                                 Ok(_) => {
                                     state.rooms.remove(&storage_id);
-                                    let upload_dir = PathBuf::from("uploads").join(&storage_id);
+                                    let upload_dir = state.uploads_root.join(&storage_id);
                                     if upload_dir.exists()
                                         && let Err(e) = fs::remove_dir_all(upload_dir).await {
                                             state.emit_log("error", &format!("Failed to clear uploads for {}: {}", storage_id, e));
                                         }
+                                    state.collect_garbage().await;
                                     state.emit_log("info", &format!("Storage deleted: {}", storage_id));
                                     state.emit_storage_list().await;
                                     
                                     if !matches!(session, SessionState::Dashboard) 
-                                        && let Ok(list) = db::list_storages(&state.db).await {
+                                        && let Ok(list) = db::list_storages_for(&state.db, current_user).await {
                                             let resp = Message::StorageList { storages: list };
                                             if let Ok(json) = serde_json::to_string(&resp) {
                                                 tx.send(WsMessage::Text(json)).ok();
@@ -114,81 +350,217 @@ async fn handle_socket(socket: WebSocket, state: SharedState) {
                                 }
                             }
                         },
-                        Message::JoinStorage { storage_id, client_name: name } => {
-                            if let SessionState::Synced { storage_id: old_id } = &session 
-                                && let Some(old_room) = state.rooms.get(old_id) {
-                                    old_room.clients.remove(&client_id);
-                                    old_room.client_names.remove(&client_id);
-                            }
+                        Message::JoinStorage { storage_id, client_name: name, device_id, multiplex, subscription } => {
+                            let storage = match db::load_storage(&state.db, &storage_id).await {
+                                Ok(Some(storage)) => storage,
+                                Ok(None) => {
+                                    send_json(&tx, &Message::Error { message: format!("Unknown storage: {}", storage_id) });
+                                    continue;
+                                }
+                                Err(e) => {
+                                    send_json(&tx, &Message::Error { message: format!("Join failed: {}", e) });
+                                    continue;
+                                }
+                            };
+                            let role = match db::storage_role(&state.db, &storage_id, current_user).await {
+                                Ok(Some(role)) => role,
+                                Ok(None) => {
+                                    send_json(&tx, &Message::Error { message: format!("Permission denied: no access to storage {}", storage_id) });
+                                    continue;
+                                }
+                                Err(e) => {
+                                    send_json(&tx, &Message::Error { message: format!("Join failed: {}", e) });
+                                    continue;
+                                }
+                            };
 
+                            let mut storages = match std::mem::replace(&mut session, SessionState::Lobby) {
+                                SessionState::Synced { storages } if multiplex => storages,
+                                SessionState::Synced { storages } => {
+                                    for old_id in storages.keys() {
+                                        if let Some(old_room) = state.rooms.get(old_id) {
+                                            old_room.clients.remove(&client_id);
+                                            old_room.client_names.remove(&client_id);
+                                        }
+                                    }
+                                    HashMap::new()
+                                }
+                                _ => HashMap::new(),
+                            };
+
+                            let subscription = if storage.encryption.as_ref().is_some_and(|e| e.encrypt_paths) {
+                                Default::default()
+                            } else {
+                                subscription
+                            };
+
+                            device.id = device_id.filter(|id| !id.is_empty() && id.len() <= 64).unwrap_or_else(|| name.clone());
                             client_name = name;
                             let room = state.get_or_load_room(&storage_id).await;
-                            room.clients.insert(client_id.clone(), tx.clone());
+                            room.clients.insert(client_id.clone(), RoomClient { tx: tx.clone(), multiplexed: multiplex, subscription: subscription.clone() });
                             room.client_names.insert(client_id.clone(), client_name.clone());
                             
                             let mut files = Vec::new();
-                            for entry in room.files.iter() {
+                            for entry in room.files.iter().filter(|entry| subscription.covers(entry.key())) {
                                 files.push(entry.value().clone());
                             }
-                            let welcome = Message::Welcome { storage_id: storage_id.clone(), files };
+                            let ignore = db::load_ignore_patterns(&state.db, &storage_id).await.unwrap_or_default();
+                            let welcome = Message::Welcome { storage_id: storage_id.clone(), files, encryption: storage.encryption, ignore };
                             if let Ok(json) = serde_json::to_string(&welcome) {
                                 tx.send(WsMessage::Text(json)).ok();
                             }
                             
-                            session = SessionState::Synced { storage_id: storage_id.clone() };
-                            state.emit_log("info", &format!("{} joined storage {}", client_name, storage_id));
+                            storages.insert(storage_id.clone(), role);
+                            session = SessionState::Synced { storages };
+                            state.emit_log("info", &format!("{} ({}) joined storage {} as {}", client_name, current_user.name, storage_id, role.as_str()));
                             state.emit_stats();
                         },
-                        Message::StartTransfer { path, size, target_version } => {
-                            if let SessionState::Synced { storage_id } = &session {
+                        Message::StartTransfer { path, size, target_version, hash, chunks } => {
+                            if let Some((storage_id, role)) = &target {
+                                if !permitted(&out, Some(*role), Role::Write, "StartTransfer") { continue; }
+                                if hash.is_empty() || !chunks_are_contiguous(&chunks, size) {
+                                    out.send(Message::Error { message: format!("Invalid chunk list for {}", path) });
+                                    continue;
+                                }
+
                                 let room = state.get_or_load_room(storage_id).await;
+                                let current = room.files.get(&path).map(|m| m.clone());
+                                if let Some(current) = current.filter(|m| !m.is_deleted) {
+                                    if current.hash == hash {
+                                        db::save_base(&state.db, storage_id, &device, &path, current.version).await.ok();
+                                        out.send(Message::FileUpdate { meta: current });
+                                        continue;
+                                    }
+
+                                    let base_version = if target_version == 0 {
+                                        db::load_base(&state.db, storage_id, &device, &path).await.ok().flatten()
+                                    } else {
+                                        Some(target_version - 1)
+                                    };
+                                    if let Some(base) = base_version && base < current.version {
+                                        state.emit_log("warn", &format!("{} uploaded {} based on v{}, server has v{}", client_name, path, base, current.version));
+                                        send_conflict(&state, &out, storage_id, path, current.version, Some(base)).await;
+                                        continue;
+                                    }
+                                }
+
                                 let effective_version = if target_version == 0 {
                                     room.files.get(&path).map(|e| e.version + 1).unwrap_or(1)
                                 } else { target_version };
 
+                                let mut missing = VecDeque::new();
+                                let mut seen = HashSet::new();
+                                for chunk in &chunks {
+                                    if seen.insert(chunk.hash.clone()) && !state.blocks.claim(&chunk.hash).await {
+                                        missing.push_back(chunk.clone());
+                                    }
+                                }
+                                if missing.len() < seen.len() {
+                                    state.emit_log("info", &format!("Upload of {}: {} of {} chunks already stored", path, seen.len() - missing.len(), seen.len()));
+                                }
+
                                 let meta = FileMetadata {
                                     path: path.clone(),
                                     size,
                                     modified: chrono::Utc::now().timestamp() as u64,
                                     version: effective_version,
-                                    hash: String::new(),
+                                    hash,
                                     is_deleted: false,
                                     last_modified_by: Some(client_name.clone()),
+                                    chunks,
                                 };
-                                transfer_state = TransferState::ExpectingBinary { path, meta };
+
+                                let uploads = uploads.entry(storage_id.clone()).or_default();
+                                uploads.remove(&path);
+                                let upload = PendingUpload { meta, missing, requested: Vec::new() };
+                                advance_upload(&state, storage_id, &client_id, &device, &out, uploads, upload).await;
                             }
                         },
-                        Message::RequestFile { path } => {
-                            if let SessionState::Synced { storage_id } = &session {
-                                let upload_dir = PathBuf::from("uploads").join(storage_id);
-                                let file_path = upload_dir.join(&path);
+                        Message::TransferChunk { path, hash, .. } => {
+                            transfer_state = TransferState::ExpectingChunk { scope, path, hash };
+                        },
+                        Message::RequestChunks { path, chunks } => {
+                            if let Some((storage_id, _)) = &target {
+                                if !chunks.iter().all(|c| blocks::is_block_hash(&c.hash)) {
+                                    out.send(Message::Error { message: format!("Invalid chunk list for {}", path) });
+                                    continue;
+                                }
                                 let room = state.get_or_load_room(storage_id).await;
+                                let mut known: HashSet<String> = room.files.get(&path)
+                                    .map(|meta| meta.chunks.iter().map(|c| c.hash.clone()).collect())
+                                    .unwrap_or_default();
+                                if chunks.iter().any(|c| !known.contains(&c.hash))
+                                    && let Ok(versions) = db::list_versions(&state.db, storage_id, &path).await {
+                                        known.extend(versions.into_iter().flat_map(|v| v.chunks).map(|c| c.hash));
+                                    }
 
-                                if let Some(meta) = room.files.get(&path) {
-                                    match fs::read(&file_path).await {
-                                        Ok(content) => {
-                                            let header = Message::StartTransfer { 
-                                                path: path.clone(), 
-                                                size: meta.size, 
-                                                target_version: meta.version 
-                                            };
-                                            if let Ok(json) = serde_json::to_string(&header) {
-                                                tx.send(WsMessage::Text(json)).ok();
-                                                tx.send(WsMessage::Binary(content)).ok();
-                                                state.emit_log("info", &format!("Serving file {} to {}", path, client_name));
-                                            }
-                                        }
-                                        Err(e) => {
-                                            state.emit_log("error", &format!("Missing file content on disk for {}: {}", path, e));
+                                for chunk in &chunks {
+                                    if known.contains(&chunk.hash) {
+                                        send_chunk(&state, &out, &path, chunk, 0).await;
+                                    } else {
+                                        state.emit_log("warn", &format!("Client requested unknown chunk of {}", path));
+                                    }
+                                }
+                            }
+                        },
+                        Message::RequestFile { path, offset } => {
+                            if let Some((storage_id, _)) = &target {
+                                let room = state.get_or_load_room(storage_id).await;
+                                let meta = room.files.get(&path).map(|m| m.clone());
+
+                                if let Some(meta) = meta {
+                                    if offset == 0 {
+                                        state.emit_log("info", &format!("Serving file {} to {}", path, client_name));
+                                    }
+                                    let chunk = meta.chunks.iter()
+                                        .find(|c| offset >= c.offset && offset < c.offset + c.size);
+                                    match chunk {
+                                        Some(chunk) => send_chunk(&state, &out, &path, chunk, offset - chunk.offset).await,
+                                        None if offset == 0 => {
+                                            out.send(Message::TransferChunk { path: path.clone(), offset, hash: common::calculate_hash(&[]) });
+                                            out.send_binary(Vec::new());
                                         }
+                                        None => {}
                                     }
                                 } else {
                                     state.emit_log("warn", &format!("Client requested unknown file: {}", path));
                                 }
                             }
                         },
+                        Message::Synced { path, version } => {
+                            if let Some((storage_id, _)) = &target
+                                && let Err(e) = db::save_base(&state.db, storage_id, &device, &path, version).await {
+                                    state.emit_log("error", &format!("Failed to record base of {}: {}", path, e));
+                                }
+                        },
+                        Message::ListVersions { path } => {
+                            if let Some((storage_id, _)) = &target {
+                                match db::list_versions(&state.db, storage_id, &path).await {
+                                    Ok(versions) => out.send(Message::VersionList { path, versions }),
+                                    Err(e) => out.send(Message::Error { message: format!("Failed to list versions: {}", e) }),
+                                }
+                            }
+                        },
+                        Message::RestoreVersion { path, version } => {
+                            if let Some((storage_id, role)) = &target {
+                                if !permitted(&out, Some(*role), Role::Write, "RestoreVersion") { continue; }
+                                match state.restore_version(storage_id, &path, version, &client_name).await {
+                                    Ok(meta) => {
+                                        let msg = if meta.is_deleted {
+                                            Message::DeleteFile { path: meta.path }
+                                        } else {
+                                            Message::FileUpdate { meta }
+                                        };
+                                        state.broadcast(storage_id, &client_id, &msg).await;
+                                        out.send(msg);
+                                    }
+                                    Err(message) => out.send(Message::Error { message }),
+                                }
+                            }
+                        },
                         Message::DeleteFile { path } => {
-                            if let SessionState::Synced { storage_id } = &session {
+                            if let Some((storage_id, role)) = &target {
+                                if !permitted(&out, Some(*role), Role::Write, "DeleteFile") { continue; }
                                 let room = state.get_or_load_room(storage_id).await;
                                 let version = room.files.get(&path).map(|m| m.version + 1).unwrap_or(1);
                                 let meta = FileMetadata {
@@ -199,10 +571,11 @@ async fn handle_socket(socket: WebSocket, state: SharedState) {
                                     hash: String::new(),
                                     is_deleted: true,
                                     last_modified_by: Some(client_name.clone()),
+                                    chunks: Vec::new(),
                                 };
-                                if let Some(updated) = state.process_update(storage_id, meta).await 
-                                    && let Ok(json) = serde_json::to_string(&Message::DeleteFile { path: updated.path }) {
-                                        state.broadcast(storage_id, &client_id, WsMessage::Text(json)).await;
+                                if let Some(updated) = state.process_update(storage_id, meta).await {
+                                    db::save_base(&state.db, storage_id, &device, &updated.path, updated.version).await.ok();
+                                    state.broadcast(storage_id, &client_id, &Message::DeleteFile { path: updated.path }).await;
                                 }
                             }
                         }
@@ -211,45 +584,23 @@ async fn handle_socket(socket: WebSocket, state: SharedState) {
                 }
             },
             WsMessage::Binary(data) => {
-                if let SessionState::Synced { storage_id } = &session 
-                    && let TransferState::ExpectingBinary { path, mut meta } = transfer_state {
-                        meta.hash = common::calculate_hash(&data);
-                        if let Some(updated_meta) = state.process_update(storage_id, meta).await {
-                            let upload_dir = PathBuf::from("uploads").join(storage_id);
-                            let file_path = upload_dir.join(&path);
-                            
-                            if let Some(parent) = file_path.parent() 
-                                && let Err(e) = fs::create_dir_all(parent).await {
-                                    state.emit_log("error", &format!("Failed to create dir for {}: {}", path, e));
-                                }
-                            if let Err(e) = fs::write(&file_path, &data).await {
-                                state.emit_log("error", &format!("Failed to write file {}: {}", path, e));
-                            }
-
-                            let header = Message::StartTransfer { 
-                                path: updated_meta.path.clone(), 
-                                size: updated_meta.size, 
-                                target_version: updated_meta.version 
-                            };
-                            if let Ok(json) = serde_json::to_string(&header) {
-                                state.broadcast(storage_id, &client_id, WsMessage::Text(json)).await;
-                                state.broadcast(storage_id, &client_id, WsMessage::Binary(data)).await;
-                            }
-
-                            let update_msg = Message::FileUpdate { meta: updated_meta };
-                            if let Ok(json) = serde_json::to_string(&update_msg) {
-                                state.broadcast(storage_id, &client_id, WsMessage::Text(json)).await;
-                            }
-                        } else {
-                            let room = state.get_or_load_room(storage_id).await;
-                            if let Some(current) = room.files.get(&path) {
-                                let err = Message::ConflictDetected { path: path.clone(), server_version: current.version };
-                                if let Ok(json) = serde_json::to_string(&err) {
-                                    tx.send(WsMessage::Text(json)).ok();
-                                }
+                if let TransferState::ExpectingChunk { scope, path, hash } = std::mem::replace(&mut transfer_state, TransferState::Idle)
+                    && let Some((storage_id, _)) = session.joined(scope.as_deref())
+                    && let Some(uploads) = uploads.get_mut(&storage_id)
+                    && let Some(mut upload) = uploads.remove(&path) {
+                        let out = Outbox { tx: &tx, scope: scope.as_deref() };
+                        if let Some(pos) = upload.requested.iter().position(|c| c.hash == hash) {
+                            let chunk = upload.requested.remove(pos);
+                            if common::calculate_hash(&data) != hash {
+                                upload.missing.push_back(chunk);
+                            } else if let Err(e) = state.blocks.put(&hash, &data).await {
+                                state.emit_log("error", &format!("Failed to store chunk of {}: {}", path, e));
+                                out.send(Message::Error { message: format!("Upload of {} failed: could not store chunk", path) });
+                                drop(upload);
+                                continue;
                             }
                         }
-                        transfer_state = TransferState::Idle;
+                        advance_upload(&state, &storage_id, &client_id, &device, &out, uploads, upload).await;
                     }
             }
             _ => {}
@@ -259,13 +610,15 @@ async fn handle_socket(socket: WebSocket, state: SharedState) {
     state.dashboards.remove(&dashboard_id);
 
     match session {
-        SessionState::Synced { storage_id } => {
-             if let Some(room) = state.rooms.get(&storage_id) {
-                 room.clients.remove(&client_id);
-                 room.client_names.remove(&client_id);
-                 state.emit_stats();
-                 state.emit_log("info", &format!("Client disconnected from {}", storage_id));
-             }
+        SessionState::Synced { storages } => {
+            for storage_id in storages.keys() {
+                if let Some(room) = state.rooms.get(storage_id) {
+                    room.clients.remove(&client_id);
+                    room.client_names.remove(&client_id);
+                    state.emit_log("info", &format!("Client disconnected from {}", storage_id));
+                }
+            }
+            state.emit_stats();
         }
         SessionState::Dashboard => {
             state.emit_stats();